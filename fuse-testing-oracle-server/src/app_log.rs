@@ -0,0 +1,86 @@
+use std::sync::{Arc,Mutex};
+use std::fmt;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq)]
+pub enum LogSource {
+    Stdout,
+    Stderr,
+    //sent by GUIOracleClient as Log messages
+    Client,
+}
+
+impl fmt::Display for LogSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &LogSource::Stdout => write!(f, "stdout"),
+            &LogSource::Stderr => write!(f, "stderr"),
+            &LogSource::Client => write!(f, "client"),
+        }
+    }
+}
+
+#[derive(Debug,Clone)]
+pub struct LogLine {
+    pub source: LogSource,
+    //the screen size that was being captured when the line came in, None on startup and shutdown
+    pub screen_size: Option<String>,
+    pub text: String,
+}
+
+impl fmt::Display for LogLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.screen_size {
+            Some(ref screen_size) => write!(f, "[{}] {}: {}", screen_size, self.source, self.text),
+            None => write!(f, "[-] {}: {}", self.source, self.text),
+        }
+    }
+}
+
+struct AppLogState {
+    screen_size: Option<String>,
+    lines: Vec<LogLine>,
+}
+
+/*Everything one app instance printed or logged, kept out of the oracle's own output.
+It is written to from the threads reading the app's stdout and stderr and from the server
+thread, so clones share the same lines.*/
+#[derive(Clone)]
+pub struct AppLog {
+    state: Arc<Mutex<AppLogState>>,
+}
+
+impl AppLog {
+    pub fn new() -> AppLog {
+        AppLog {
+            state: Arc::new(Mutex::new(AppLogState {
+                screen_size: None,
+                lines: Vec::new(),
+            })),
+        }
+    }
+
+    //lines added from now on are tagged with this screen size
+    pub fn set_screen_size(&self, screen_size: Option<String>) {
+        self.state.lock().unwrap().screen_size = screen_size;
+    }
+
+    pub fn add(&self, source: LogSource, text: String) {
+        let mut state = self.state.lock().unwrap();
+        let screen_size = state.screen_size.clone();
+        state.lines.push(LogLine {
+            source: source,
+            screen_size: screen_size,
+            text: text,
+        });
+    }
+
+    pub fn lines(&self) -> Vec<LogLine> {
+        self.state.lock().unwrap().lines.clone()
+    }
+}
+
+impl fmt::Debug for AppLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AppLog({} lines)", self.state.lock().unwrap().lines.len())
+    }
+}
@@ -1,5 +1,5 @@
 use std::collections::{HashMap,HashSet};
-use super::{LayoutViolation,LayoutViolations};
+use layout_validator::validity_rules::{LayoutViolation,LayoutViolations};
 
 pub struct Baseline {
     pub violations_to_accept: Vec<LayoutViolation>
@@ -12,6 +12,7 @@ pub fn find_baseline(violations: &LayoutViolations, n_test_sets: i32,
     let mut overlaps_map = HashMap::new();
     let mut overflows_map = HashMap::new();
     let mut alignment_changes_map = HashMap::new();
+    let mut custom_map = HashMap::new();
 
     for v in violations.all() {
         match v {
@@ -47,6 +48,16 @@ pub fn find_baseline(violations: &LayoutViolations, n_test_sets: i32,
                     alignment_changes_map.get_mut(&key).unwrap().push(val);
                 }
             },
+            //a moving node is a finding on its own, it is never part of the baseline
+            LayoutViolation::UnstableLayout { .. } => (),
+            //violations of other rules are baselined like overlaps, by how many test sets they show up in
+            LayoutViolation::Custom(_) => {
+                if !custom_map.contains_key(&v) {
+                    custom_map.insert(v.clone(),0);
+                }
+                let c = custom_map.get_mut(&v).unwrap();
+                *c = *c + 1;
+            },
         }
     }
 
@@ -66,6 +77,12 @@ pub fn find_baseline(violations: &LayoutViolations, n_test_sets: i32,
             }
         }
 
+        for (o,c) in custom_map {
+            if c >= oo_threshold {
+                violations_to_accept.push(o.clone());
+            }
+        }
+
     }
 
     println!("NTestSets: {}", n_test_sets);
@@ -99,25 +116,36 @@ pub fn find_baseline(violations: &LayoutViolations, n_test_sets: i32,
     }
 }
 
+//the removed violations are kept in violations.baselined
 pub fn remove_baseline_violations(violations: &mut LayoutViolations, baseline: &Baseline) {
 
     for ref v in &baseline.violations_to_accept {
         if violations.overflows.contains(&v) {
             while let Some(index) = violations.overflows.iter().position(|x| *x == **v) {
                 println!("removing overflow : {}", index);
-                violations.overflows.remove(index);
+                let removed = violations.overflows.remove(index);
+                violations.baselined.push(removed);
             }
         }
         else if violations.overlaps.contains(&v) {
             while let Some(index) = violations.overlaps.iter().position(|x| *x == **v) {
                 println!("removing overlaps : {}", index);
-                violations.overlaps.remove(index);
+                let removed = violations.overlaps.remove(index);
+                violations.baselined.push(removed);
             }
         }
         else if violations.alignment_changes.contains(&v) {
             while let Some(index) = violations.alignment_changes.iter().position(|x| *x == **v) {
                 println!("removing alignment_changes : {}", index);
-                violations.alignment_changes.remove(index);
+                let removed = violations.alignment_changes.remove(index);
+                violations.baselined.push(removed);
+            }
+        }
+        else if violations.custom.contains(&v) {
+            while let Some(index) = violations.custom.iter().position(|x| *x == **v) {
+                println!("removing {} : {}", v.kind(), index);
+                let removed = violations.custom.remove(index);
+                violations.baselined.push(removed);
             }
         }
     }
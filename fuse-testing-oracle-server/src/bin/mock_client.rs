@@ -0,0 +1,297 @@
+/*Stands in for a Fuse app running GUIOracleClient.uno, so the server and the auto run can be
+exercised without Fuse. It answers layout requests either from fixture json files (bare
+LayoutChanged payloads or layouts recorded with --record) or from a small procedural generator.*/
+
+extern crate fuse_testing_oracle_server;
+extern crate serde_json;
+extern crate clap;
+extern crate rand;
+
+use fuse_testing_oracle_server::{MessageType, MessageData};
+use fuse_testing_oracle_server::codec::MessageCodec;
+use fuse_testing_oracle_server::protocol::{Hello, Welcome, SESSION_ENV_VAR};
+use fuse_testing_oracle_server::server::{JSONNode, LayoutChanged, ScreenSize};
+
+use std::env;
+use std::fs::{self,File};
+use std::io::{Read,Write};
+use std::net::TcpStream;
+use std::path::{Path,PathBuf};
+
+use serde_json::Value;
+
+use clap::{Arg, App};
+
+use rand::{Rng, SeedableRng, StdRng};
+
+enum LayoutSource {
+    Fixtures(Vec<Value>),
+    Generator(usize),
+}
+
+fn load_fixtures(dir: &Path) -> Vec<Value> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .expect("Could not read the fixtures directory")
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+        .collect();
+    paths.sort();
+
+    let mut fixtures = Vec::new();
+    for path in paths {
+        let mut json_string = String::new();
+        File::open(&path).unwrap().read_to_string(&mut json_string).unwrap();
+        let value: Value = serde_json::from_str(&json_string).unwrap();
+
+        //layouts recorded by the server wrap the payload together with the device info
+        let layout = match value.pointer("/Layout") {
+            Some(layout) => layout.clone(),
+            None => value.clone(),
+        };
+        println!("Loaded fixture {:?}", path);
+        fixtures.push(layout);
+    }
+    fixtures
+}
+
+fn fixture_size(fixture: &Value) -> Option<(i32,i32)> {
+    let w = fixture.pointer("/ScreenSize/W").and_then(|w| w.as_f64());
+    let h = fixture.pointer("/ScreenSize/H").and_then(|h| h.as_f64());
+    match (w, h) {
+        (Some(w), Some(h)) => Some((w as i32, h as i32)),
+        _ => None,
+    }
+}
+
+fn leaf(name: String, line: i32, x: i32, y: i32, w: i32, h: i32) -> JSONNode {
+    JSONNode {
+        children: Vec::new(),
+        name: name,
+        line: line,
+        file: "Generated.ux".to_string(),
+        actual_position_x: x,
+        actual_position_y: y,
+        actual_width: w,
+        actual_height: h,
+        render_width: w,
+        render_height: h,
+        render_position_x: x,
+        render_position_y: y,
+    }
+}
+
+/*Builds a list page for the given root size. The structure only depends on the seed, so every
+screen size gets the same node ids, while the geometry depends on the size. Some rows get a label
+with a minimum width (overflows on narrow screens), a badge at a fixed position (overlaps on wide
+screens) or an icon that moves above the label on narrow screens (alignment changes).*/
+fn generate_layout(seed: usize, size: (i32,i32)) -> JSONNode {
+    let seed_slice: &[usize] = &[seed];
+    let mut rng: StdRng = SeedableRng::from_seed(seed_slice);
+    let (w, h) = size;
+
+    let mut line = 1;
+    let mut next_line = || { line += 1; line };
+
+    let mut root = leaf("Root".to_string(), next_line(), 0, 0, w, h);
+
+    let header_height = 56;
+    let mut header = leaf("Header".to_string(), next_line(), 0, 0, w, header_height);
+    header.children.push(leaf("Title".to_string(), next_line(), 16, 16, w - 32, 24));
+    root.children.push(header);
+
+    let n_rows = rng.gen_range(3, 8);
+    let row_height = 64;
+    let mut y = header_height;
+    for i in 0..n_rows {
+        let fixed_label = rng.gen_weighted_bool(3);
+        let badge = rng.gen_weighted_bool(4);
+        let wrapping = rng.gen_weighted_bool(3);
+
+        let narrow = w < 360;
+        let this_row_height = if wrapping && narrow { row_height * 2 } else { row_height };
+        let mut row = leaf(format!("Row{}", i), next_line(), 0, y, w, this_row_height);
+
+        let (icon_x, icon_y, label_x, label_y) = if wrapping && narrow {
+            (8, y + 8, 8, y + 8 + 48)
+        } else {
+            (8, y + 8, 64, y + 20)
+        };
+        row.children.push(leaf(format!("Icon{}", i), next_line(), icon_x, icon_y, 48, 48));
+
+        let mut label_width = if badge { (w - 72) / 2 } else { w - label_x - 8 };
+        if fixed_label && label_width < 220 {
+            label_width = 220;
+        }
+        row.children.push(leaf(format!("Label{}", i), next_line(), label_x, label_y, label_width, 24));
+
+        if badge {
+            row.children.push(leaf(format!("Badge{}", i), next_line(), 260, y + 20, 40, 24));
+        }
+
+        root.children.push(row);
+        y += this_row_height;
+    }
+
+    root
+}
+
+fn send(stream: &mut TcpStream, message_type: MessageType, data: &str) {
+    //the app sends its payload as is, only the server wraps it in a MessageData
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(format!("{}\n{}\n", message_type.as_i32(), data.len()).as_bytes());
+    bytes.extend_from_slice(data.as_bytes());
+    stream.write_all(bytes.as_slice()).unwrap();
+}
+
+fn layout_for_request(source: &mut LayoutSource, next_fixture: &mut usize, id: i32, size: (i32,i32)) -> String {
+    match source {
+        &mut LayoutSource::Fixtures(ref fixtures) => {
+            //prefer the fixture recorded at the current size, otherwise serve them in order
+            let index = match fixtures.iter().position(|f| fixture_size(f) == Some(size)) {
+                Some(index) => index,
+                None => *next_fixture % fixtures.len(),
+            };
+            *next_fixture = index + 1;
+
+            let mut layout = fixtures[index].clone();
+            if let Some(object) = layout.as_object_mut() {
+                object.insert("Id".to_string(), serde_json::to_value(id).unwrap());
+            }
+            serde_json::to_string(&layout).unwrap()
+        },
+        &mut LayoutSource::Generator(seed) => {
+            let nodes = generate_layout(seed, size);
+            let layout_changed = LayoutChanged::new(id, ScreenSize::new(size.0 as f32, size.1 as f32), nodes);
+            serde_json::to_string(&layout_changed).unwrap()
+        },
+    }
+}
+
+fn main() {
+    let matches = App::new("Fuse layout testing oracle mock client")
+        .version("0.0")
+        .about("Connects to the oracle server and answers its requests the way GUIOracleClient.uno would.")
+        .arg(Arg::with_name("address")
+             .short("a")
+             .long("address")
+             .takes_value(true)
+             .help("Address of the oracle server, defaults to 127.0.0.1:12345"))
+        .arg(Arg::with_name("fixtures")
+             .short("f")
+             .long("fixtures")
+             .takes_value(true)
+             .help("Directory of layout json files to answer with"))
+        .arg(Arg::with_name("generate")
+             .short("g")
+             .long("generate")
+             .takes_value(true)
+             .help("Answers with generated layouts, using the given seed"))
+        .arg(Arg::with_name("size")
+             .short("s")
+             .long("size")
+             .takes_value(true)
+             .help("Initial root size in points, as WxH. Defaults to 375x667"))
+        .arg(Arg::with_name("exit_after")
+             .long("exit-after")
+             .takes_value(true)
+             .help("Exits without a word after answering this many layout requests, to test crash recovery"))
+        .get_matches();
+
+    let address = matches.value_of("address").unwrap_or("127.0.0.1:12345");
+
+    let mut source = if let Some(fixtures_dir) = matches.value_of("fixtures") {
+        let fixtures = load_fixtures(Path::new(fixtures_dir));
+        if fixtures.len() == 0 {
+            panic!("Found no fixtures in {}", fixtures_dir);
+        }
+        LayoutSource::Fixtures(fixtures)
+    } else {
+        let seed = matches.value_of("generate").map_or(0, |s| s.parse::<usize>().unwrap());
+        LayoutSource::Generator(seed)
+    };
+
+    let exit_after = matches.value_of("exit_after").map(|n| n.parse::<usize>().unwrap());
+
+    let mut size = (375, 667);
+    if let Some(s) = matches.value_of("size") {
+        let parts: Vec<i32> = s.split('x').map(|p| p.parse::<i32>().unwrap()).collect();
+        size = (parts[0], parts[1]);
+    }
+
+    println!("Mock client connecting to {}", address);
+    let mut stream = TcpStream::connect(address).unwrap();
+    println!("Connected");
+
+    //set by the test runner, so the server can tell the instances it started apart
+    let session = env::var(SESSION_ENV_VAR).ok();
+    let hello = serde_json::to_string(&Hello::new(session)).unwrap();
+    send(&mut stream, MessageType::Hello, &hello);
+
+    let mut codec = MessageCodec::new();
+    let mut next_fixture = 0;
+    let mut layouts_sent = 0;
+    loop {
+        let mut bytes = [0;2048];
+        let bytes_read = match stream.read(&mut bytes) {
+            Ok(0) | Err(_) => {
+                println!("Server closed the connection");
+                break;
+            },
+            Ok(bytes_read) => bytes_read,
+        };
+        codec.add_to_buffer(&bytes[0..bytes_read]);
+
+        while let Some(decoded) = codec.decode() {
+            let message = match decoded {
+                Ok(message) => message,
+                Err(e) => {
+                    println!("Protocol error from server: {}", e);
+                    continue;
+                }
+            };
+            let data: MessageData = serde_json::from_str(&message.data.json_string).unwrap();
+
+            match message.message_type {
+                MessageType::RequestLayoutData => {
+                    if Some(layouts_sent) == exit_after {
+                        println!("Crashing on purpose");
+                        std::process::exit(3);
+                    }
+                    layouts_sent += 1;
+                    let id = data.json_string.parse::<i32>().unwrap();
+                    println!("Got layout request {} at {:?}", id, size);
+                    let layout = layout_for_request(&mut source, &mut next_fixture, id, size);
+                    send(&mut stream, MessageType::LayoutData, &layout);
+                },
+                MessageType::Welcome => {
+                    let welcome: Welcome = serde_json::from_str(&data.json_string).unwrap();
+                    if !welcome.accepted {
+                        println!("Server rejected us: {}", welcome.reason.unwrap_or_default());
+                        return;
+                    }
+                    println!("Server accepted us with protocol version {}, capabilities {:?}", welcome.protocol_version, welcome.capabilities);
+                },
+                MessageType::LayoutReady => {
+                    //generated and fixture layouts never animate, so they are always ready
+                    let id = data.json_string.parse::<i32>().unwrap();
+                    send(&mut stream, MessageType::LayoutReady, &format!("{{\"Id\": {}}}", id));
+                },
+                MessageType::Heartbeat => {
+                    send(&mut stream, MessageType::Heartbeat, &data.json_string);
+                },
+                MessageType::ResizeWindow => {
+                    let new_size: ScreenSize = serde_json::from_str(&data.json_string).unwrap();
+                    size = new_size.size();
+                    println!("Resizing to {:?}", size);
+                    send(&mut stream, MessageType::Log, &format!("{{\"Message\": \"resized to {}x{}\"}}", size.0, size.1));
+                    let ack = serde_json::to_string(&ScreenSize::new(size.0 as f32, size.1 as f32)).unwrap();
+                    send(&mut stream, MessageType::ResizeWindow, &ack);
+                },
+                _ => {
+                    println!("Ignoring message {:?}", message.message_type);
+                }
+            }
+        }
+    }
+}
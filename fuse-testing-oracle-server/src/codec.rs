@@ -0,0 +1,262 @@
+use std::error::Error;
+use std::fmt;
+use std::str;
+
+use super::{Message, MessageType, MessageData};
+
+//the type and length lines are short numbers, anything longer means we are not looking at a frame header
+const MAX_HEADER_LINE_LENGTH: usize = 16;
+//the biggest payload we buffer, far more than the layout of any app. A longer one means a broken or hostile client
+pub const MAX_FRAME_LENGTH: i32 = 64 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum CodecError {
+    //the stream is out of sync, the buffered bytes have been dropped
+    InvalidHeader(String),
+    //a well formed frame with a type we don't know, the frame has been skipped
+    UnknownMessageType(i32),
+    //a well formed frame whose payload is not utf8, the frame has been skipped
+    InvalidPayload(MessageType),
+}
+
+impl CodecError {
+    //after an invalid header we can't find the start of the next frame, so the connection is useless
+    pub fn is_recoverable(&self) -> bool {
+        match self {
+            &CodecError::InvalidHeader(_) => false,
+            &CodecError::UnknownMessageType(_) => true,
+            &CodecError::InvalidPayload(_) => true,
+        }
+    }
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &CodecError::InvalidHeader(ref header) => write!(f, "invalid frame header: {:?}", header),
+            &CodecError::UnknownMessageType(message_type) => write!(f, "unknown message type: {}", message_type),
+            &CodecError::InvalidPayload(ref message_type) => write!(f, "payload of {:?} message is not valid utf8", message_type),
+        }
+    }
+}
+
+impl Error for CodecError {
+    fn description(&self) -> &str {
+        match self {
+            &CodecError::InvalidHeader(_) => "invalid frame header",
+            &CodecError::UnknownMessageType(_) => "unknown message type",
+            &CodecError::InvalidPayload(_) => "payload is not valid utf8",
+        }
+    }
+}
+
+/*Decodes the "type\nlength\npayload" frames of the protocol from a byte stream.
+Frames can be split over any number of reads, bytes are kept until a whole frame has arrived.*/
+pub struct MessageCodec {
+    buffer: Vec<u8>,
+}
+
+impl MessageCodec {
+    pub fn new() -> MessageCodec {
+        MessageCodec {
+            buffer: Vec::new()
+        }
+    }
+
+    pub fn add_to_buffer(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    fn header_line(&self, start: usize) -> Option<Result<(i32, usize), CodecError>> {
+        let line_end = match self.buffer[start..].iter().position(|&b| b == b'\n') {
+            Some(i) => start + i,
+            None => {
+                if self.buffer.len() - start > MAX_HEADER_LINE_LENGTH {
+                    let header = String::from_utf8_lossy(&self.buffer[start..]).into_owned();
+                    return Some(Err(CodecError::InvalidHeader(header)));
+                }
+                return None;
+            }
+        };
+
+        let line = &self.buffer[start..line_end];
+        let value = str::from_utf8(line).ok().and_then(|s| s.trim().parse::<i32>().ok());
+        match value {
+            Some(value) => Some(Ok((value, line_end + 1))),
+            None => Some(Err(CodecError::InvalidHeader(String::from_utf8_lossy(line).into_owned()))),
+        }
+    }
+
+    /*Returns None until a whole frame is buffered. Errors are returned one at a time,
+    so the caller can report them and keep decoding if they are recoverable.*/
+    pub fn decode(&mut self) -> Option<Result<Message, CodecError>> {
+        let (message_type_value, length_start) = match self.header_line(0) {
+            Some(Ok(header)) => header,
+            Some(Err(e)) => {
+                self.buffer.clear();
+                return Some(Err(e));
+            },
+            None => return None,
+        };
+
+        let (message_length, data_start) = match self.header_line(length_start) {
+            Some(Ok((length, _))) if length < 0 || length > MAX_FRAME_LENGTH => {
+                self.buffer.clear();
+                return Some(Err(CodecError::InvalidHeader(format!("{}", length))));
+            },
+            Some(Ok(header)) => header,
+            Some(Err(e)) => {
+                self.buffer.clear();
+                return Some(Err(e));
+            },
+            None => return None,
+        };
+
+        let frame_end = data_start + message_length as usize;
+        if self.buffer.len() < frame_end {
+            return None;
+        }
+
+        let frame: Vec<u8> = self.buffer.drain(0..frame_end).collect();
+        let data = frame[data_start..].to_vec();
+
+        let message_type = match MessageType::from_i32(message_type_value) {
+            Some(message_type) => message_type,
+            None => return Some(Err(CodecError::UnknownMessageType(message_type_value))),
+        };
+
+        match String::from_utf8(data) {
+            Ok(data_string) => Some(Ok(Message {
+                message_type: message_type,
+                length: message_length,
+                data: MessageData::from_json_string(data_string),
+            })),
+            Err(_) => Some(Err(CodecError::InvalidPayload(message_type))),
+        }
+    }
+
+    pub fn decode_messages(&mut self) -> Vec<Result<Message, CodecError>> {
+        let mut ret = Vec::new();
+        while let Some(result) = self.decode() {
+            ret.push(result);
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expect_message(result: Option<Result<Message, CodecError>>, json_string: &str) -> MessageType {
+        match result {
+            Some(Ok(message)) => {
+                assert_eq!(message.data.json_string, json_string);
+                assert_eq!(message.length, json_string.len() as i32);
+                message.message_type
+            },
+            Some(Err(e)) => panic!("expected a message, got {}", e),
+            None => panic!("expected a message, got nothing"),
+        }
+    }
+
+    #[test]
+    fn frame_split_across_reads() {
+        let mut codec = MessageCodec::new();
+        codec.add_to_buffer(b"0");
+        assert!(codec.decode().is_none());
+        codec.add_to_buffer(b"\n1");
+        assert!(codec.decode().is_none());
+        codec.add_to_buffer(b"0\n{\"a\":");
+        assert!(codec.decode().is_none());
+        codec.add_to_buffer(b"\"bc\"}");
+        match expect_message(codec.decode(), "{\"a\":\"bc\"}") {
+            MessageType::LayoutData => (),
+            other => panic!("expected LayoutData, got {:?}", other),
+        }
+        assert_eq!(codec.buffered_len(), 0);
+    }
+
+    #[test]
+    fn two_frames_in_one_read() {
+        let mut codec = MessageCodec::new();
+        codec.add_to_buffer(b"5\n2\n{}6\n0\n3\n");
+        match expect_message(codec.decode(), "{}") {
+            MessageType::LayoutReady => (),
+            other => panic!("expected LayoutReady, got {:?}", other),
+        }
+        match expect_message(codec.decode(), "") {
+            MessageType::Heartbeat => (),
+            other => panic!("expected Heartbeat, got {:?}", other),
+        }
+        //the start of a third frame stays buffered
+        assert!(codec.decode().is_none());
+        assert_eq!(codec.buffered_len(), 2);
+    }
+
+    #[test]
+    fn unknown_type_is_skipped() {
+        let mut codec = MessageCodec::new();
+        codec.add_to_buffer(b"42\n3\nabc7\n2\nhi");
+        let results = codec.decode_messages();
+        assert_eq!(results.len(), 2);
+        match results[0] {
+            Err(ref e @ CodecError::UnknownMessageType(42)) => assert!(e.is_recoverable()),
+            ref other => panic!("expected an unknown type, got {:?}", other),
+        }
+        match results[1] {
+            Ok(ref message) => assert_eq!(message.data.json_string, "hi"),
+            ref other => panic!("expected the log message, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bad_header_drops_the_buffer() {
+        let mut codec = MessageCodec::new();
+        codec.add_to_buffer(b"layout\n2\n{}");
+        match codec.decode() {
+            Some(Err(ref e @ CodecError::InvalidHeader(_))) => assert!(!e.is_recoverable()),
+            other => panic!("expected an invalid header, got {:?}", other),
+        }
+        assert_eq!(codec.buffered_len(), 0);
+
+        //a header line without a newline that is already too long
+        codec.add_to_buffer(b"01234567890123456789");
+        match codec.decode() {
+            Some(Err(CodecError::InvalidHeader(_))) => (),
+            other => panic!("expected an invalid header, got {:?}", other),
+        }
+
+        codec.add_to_buffer(b"0\n-1\n");
+        match codec.decode() {
+            Some(Err(CodecError::InvalidHeader(_))) => (),
+            other => panic!("expected an invalid header, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn length_over_max_frame_length() {
+        let mut codec = MessageCodec::new();
+        codec.add_to_buffer(format!("0\n{}\n{{", MAX_FRAME_LENGTH + 1).as_bytes());
+        match codec.decode() {
+            Some(Err(CodecError::InvalidHeader(_))) => (),
+            other => panic!("expected an invalid header, got {:?}", other),
+        }
+        assert_eq!(codec.buffered_len(), 0);
+    }
+
+    #[test]
+    fn invalid_utf8_payload_is_skipped() {
+        let mut codec = MessageCodec::new();
+        codec.add_to_buffer(b"7\n2\n\xff\xfe7\n2\nok");
+        match codec.decode() {
+            Some(Err(ref e @ CodecError::InvalidPayload(MessageType::Log))) => assert!(e.is_recoverable()),
+            other => panic!("expected an invalid payload, got {:?}", other),
+        }
+        expect_message(codec.decode(), "ok");
+    }
+}
@@ -35,6 +35,111 @@ pub fn init_sdl() -> (Sdl, Window) {
     (sdl_context, window)
 }
 
+/*A renderer that draws into an image in memory instead of a window, with SDL's software renderer.
+It needs no display or GPU, so the violation images can be made on a headless build server.*/
+pub fn init_headless_renderer(size: (u32,u32)) -> Renderer<'static> {
+    let surface = Surface::new(size.0, size.1, PixelFormatEnum::RGBA8888).unwrap();
+    let mut renderer = Renderer::from_surface(surface).unwrap();
+    renderer.set_blend_mode(BlendMode::Blend);
+    renderer
+}
+
+//the textures belong to the renderer they were made with
+fn load_textures(renderer: &Renderer) -> HashMap<&'static str,Texture> {
+    let mut textures = HashMap::new();
+
+    let grain_surf = Surface::load_bmp("assets/grain.bmp").unwrap();
+    let pixels_surf = Surface::load_bmp("assets/pixels.bmp").unwrap();
+    let crystal_surf = Surface::load_bmp("assets/crystal.bmp").unwrap();
+
+    let mut grain_tex = renderer.create_texture_from_surface(grain_surf).unwrap();
+    let mut pixels_tex = renderer.create_texture_from_surface(pixels_surf).unwrap();
+    let mut crystal_tex = renderer.create_texture_from_surface(crystal_surf).unwrap();
+
+    grain_tex.set_blend_mode(BlendMode::Blend);
+    grain_tex.set_alpha_mod(0x99);
+
+
+    textures.insert("grain", grain_tex);
+    textures.insert("pixels", pixels_tex);
+    textures.insert("crystal", crystal_tex);
+    textures
+}
+
+/*The names of the images the save_*_violations functions write for a violation, inside the run's
+output directory, ending in the extension of the format (png or svg). The reports use them to
+link the images. Overlaps, overflows and alignment changes get one image, custom violations one
+per test set and unstable layouts none.*/
+pub fn violation_image_names(test_sets: &TestSets, violation: &LayoutViolation, extension: &str) -> Vec<String> {
+    let line_of = |test_set: &TestSetId, node: i32| {
+        test_sets.sets.get(test_set).and_then(|ts| ts.nodes.get_from_id(node)).map(|n| n.node_data.line)
+    };
+    let size_of = |test_set: &TestSetId| test_sets.sets.get(test_set).map_or((0,0), |ts| ts.screen_size);
+
+    match violation {
+        &LayoutViolation::Overlap { node1, node2, test_set, .. } => {
+            let ss = size_of(&test_set);
+            vec![format!("overlap-{}_{}-{}x{}.{}",
+                         line_of(&test_set, node1).unwrap_or(0), line_of(&test_set, node2).unwrap_or(0),
+                         ss.0, ss.1, extension)]
+        },
+        &LayoutViolation::Overflow { node1, node2, test_set, .. } => {
+            let ss = size_of(&test_set);
+            vec![format!("overflow-L{}_L{}-{}x{}.{}",
+                         line_of(&test_set, node1).unwrap_or(0), line_of(&test_set, node2).unwrap_or(0),
+                         ss.0, ss.1, extension)]
+        },
+        &LayoutViolation::AlignmentLost { a, b, ref test_sets, .. } => {
+            let test_set = test_sets[0];
+            let ss = size_of(&test_set);
+            vec![format!("alignment_change--A{}-B{}--{}x{}--L{}_L{}.{}",
+                         a.node, b.node,
+                         ss.0, ss.1,
+                         line_of(&test_set, a.node).unwrap_or(0), line_of(&test_set, b.node).unwrap_or(0),
+                         extension)]
+        },
+        &LayoutViolation::UnstableLayout { .. } => Vec::new(),
+        &LayoutViolation::Custom(ref custom) => {
+            custom.test_sets.iter().map(|test_set| {
+                let lines: Vec<String> = custom.nodes.iter()
+                    .filter_map(|node| line_of(test_set, *node))
+                    .map(|line| format!("L{}", line))
+                    .collect();
+                let ss = size_of(test_set);
+                format!("{}-{}-{}x{}.{}", custom.kind.to_lowercase(), lines.join("_"), ss.0, ss.1, extension)
+            }).collect()
+        },
+    }
+}
+
+//the name of the screen size from the test script, with its size
+pub fn screen_size_label(test_set: &TestSet, screen_size_names: &Vec<(TestSetId, String)>) -> String {
+    let (w, h) = test_set.screen_size;
+    match screen_size_names.iter().find(|&&(id, _)| id == test_set.id) {
+        Some(&(_, ref name)) => format!("{} - {}x{}", name, w, h),
+        None => format!("{}x{}", w, h),
+    }
+}
+
+/*The test sets an alignment change is drawn in, side by side: the first where the pair is
+aligned and the first where it is not. There is only the first when the nodes are missing from
+all the others. Each comes with what it shows.*/
+pub fn alignment_lost_test_sets<'t>(test_sets: &'t TestSets, violation: &LayoutViolation) -> Vec<(&'t TestSet, &'static str)> {
+    let mut ret = Vec::new();
+    if let &LayoutViolation::AlignmentLost { a, b, test_sets: ref aligned_test_sets, .. } = violation {
+        if let Some(aligned) = aligned_test_sets.get(0).and_then(|id| test_sets.sets.get(id)) {
+            ret.push((aligned, "aligned"));
+        }
+        let lost = violation.failing_test_sets(test_sets).into_iter()
+            .filter_map(|id| test_sets.sets.get(&id))
+            .find(|ts| ts.nodes.get_from_id(a.node).is_some() && ts.nodes.get_from_id(b.node).is_some());
+        if let Some(lost) = lost {
+            ret.push((lost, "alignment lost"));
+        }
+    }
+    ret
+}
+
 pub struct DrawContext<'a,'b> {
     pub camera_pos: (i32,i32),
     window_size: (i32,i32),
@@ -49,24 +154,9 @@ pub struct DrawContext<'a,'b> {
 }
 
 impl<'a,'b> DrawContext<'a,'b> {
+    //the renderer can be a window's or a headless one from init_headless_renderer
     pub fn new(window_size: (u32,u32), font: Font<'a,'b>, renderer: Renderer<'a>) -> DrawContext<'a,'b> {
-        let mut textures = HashMap::new();
-
-        let grain_surf = Surface::load_bmp("assets/grain.bmp").unwrap();
-        let pixels_surf = Surface::load_bmp("assets/pixels.bmp").unwrap();
-        let crystal_surf = Surface::load_bmp("assets/crystal.bmp").unwrap();
-
-        let mut grain_tex = renderer.create_texture_from_surface(grain_surf).unwrap();
-        let mut pixels_tex = renderer.create_texture_from_surface(pixels_surf).unwrap();
-        let mut crystal_tex = renderer.create_texture_from_surface(crystal_surf).unwrap();
-
-        grain_tex.set_blend_mode(BlendMode::Blend);
-        grain_tex.set_alpha_mod(0x99);
-
-
-        textures.insert("grain", grain_tex);
-        textures.insert("pixels", pixels_tex);
-        textures.insert("crystal", crystal_tex);
+        let textures = load_textures(&renderer);
 
         DrawContext {
             camera_pos: (0,0),
@@ -80,6 +170,19 @@ impl<'a,'b> DrawContext<'a,'b> {
         }
     }
 
+    //resizes the window, or the image a headless renderer draws into
+    pub fn set_size(&mut self, width: u32, height: u32) {
+        self.window_size = (width as i32, height as i32);
+        if self.renderer.window().is_some() {
+            self.renderer.window_mut().unwrap().set_size(width, height);
+        } else if self.renderer.output_size().unwrap() != (width, height) {
+            //the textures belong to the old renderer, so they are only made again when the size changes
+            self.textures.clear();
+            self.renderer = init_headless_renderer((width, height));
+            self.textures = load_textures(&self.renderer);
+        }
+    }
+
     pub fn set_camera(&mut self, pos: (i32,i32)) {
         self.camera_pos = pos;
     }
@@ -126,6 +229,12 @@ impl<'a,'b> DrawContext<'a,'b> {
     }
 
     pub fn draw_tab_stop(&mut self, tab_stop: &TabStop, label: String, mouse_pos: (i32,i32), mouse_clicked: bool) {
+        let root_size = self.window_size;
+        self.draw_tab_stop_within(tab_stop, label, mouse_pos, root_size);
+    }
+
+    //draws the tab stop across root_size instead of the whole window, for images with more than one screen size
+    pub fn draw_tab_stop_within(&mut self, tab_stop: &TabStop, label: String, mouse_pos: (i32,i32), root_size: (i32,i32)) {
 
         //these guys don't do anything anymore
         let mut counter = 0;
@@ -134,7 +243,6 @@ impl<'a,'b> DrawContext<'a,'b> {
 
         let pos = tab_stop.pos;
         let ref orientation = tab_stop.orientation;
-        let root_size = self.window_size;
         let c = (0x00, 0x00, 0x00);
         self.renderer.set_draw_color(Color::RGBA(c.0, c.1, c.2, 0xff));
 
@@ -154,8 +262,7 @@ impl<'a,'b> DrawContext<'a,'b> {
 
 
 
-                let mp = mouse_pos;
-                if (mp.1 - pos).abs() < 15 {
+                if tab_stop.hit_test(mouse_pos) {
                     self.renderer.set_draw_color(Color::RGBA(0x00,0x55,0x55,0xff));
                     line_width = 4;
                     mouse_over = true;
@@ -166,8 +273,7 @@ impl<'a,'b> DrawContext<'a,'b> {
                 x =  pos + self.camera_pos.0;
                 label_counter_h += 1;
 
-                let mp = mouse_pos;
-                if (mp.0 - pos).abs() < 15 {
+                if tab_stop.hit_test(mouse_pos) {
                     self.renderer.set_draw_color(Color::RGBA(0x55,0x00,0x55,0xff));
                     line_width = 4;
                     mouse_over = true;
@@ -258,7 +364,7 @@ impl<'a,'b> DrawContext<'a,'b> {
     }
 
     pub fn save_to_png(&self, path: &str) {
-        let window_size = self.renderer.window().unwrap().size();
+        let window_size = self.renderer.output_size().unwrap();
         if let Ok(mut pixels) = self.renderer.read_pixels(None, PixelFormatEnum::RGBA8888) {
             let save_surface = Surface::from_data(&mut pixels,
                                                   window_size.0,
@@ -292,6 +398,7 @@ impl<'a,'b> DrawContext<'a,'b> {
                     ref node1,
                     ref node2,
                     ref intersection_rect,
+                    ..
                 } => {
                     self.draw_rect(intersection_rect.clone(), (0,0,0,255));
                 },
@@ -300,6 +407,7 @@ impl<'a,'b> DrawContext<'a,'b> {
                     ref node1,
                     ref node2,
                     ref overflow_rect,
+                    ..
                 } => {
                     match overflow_rect {
                         &OverflowRect::Partial { left, top, right, bottom } => {
@@ -329,6 +437,16 @@ impl<'a,'b> DrawContext<'a,'b> {
                     println!("Drawing line from {:?} to {:?}", p1, p2);
                     self.draw_line((0xff,0x00,0x00), p1, p2);
                 },
+                &LayoutViolation::UnstableLayout { ref rects, .. } => {
+                    for r in rects {
+                        self.draw_rect(r.clone(), (0xcc,0x88,0x00,0x88));
+                    }
+                },
+                &LayoutViolation::Custom(ref custom) => {
+                    for r in &custom.rects {
+                        self.draw_rect(r.clone(), (0x88,0x00,0x88,0x88));
+                    }
+                },
             }
         }
     }
@@ -353,13 +471,13 @@ impl<'a,'b> DrawContext<'a,'b> {
 
 
             match violation {
-                &LayoutViolation::Overlap { node1, node2, intersection_rect, test_set } => {
+                &LayoutViolation::Overlap { node1, node2, intersection_rect, test_set, .. } => {
                     let test_set = test_sets.sets.get(&test_set).unwrap();
 
                     let ref nodes = test_set.nodes;
                     let ss = test_set.screen_size;
 
-                    self.renderer.window_mut().unwrap().set_size(ss.0 as u32, ss.1 as u32);
+                    self.set_size(ss.0 as u32, ss.1 as u32);
 
                     self.clear();
                     self.draw_nodes(&nodes);
@@ -371,10 +489,7 @@ impl<'a,'b> DrawContext<'a,'b> {
                     self.draw_node(&n2);
                     self.draw_grainy_rect(intersection_rect);
 
-                    let ref n1 = nodes.get_from_id(node1).unwrap().node_data;
-                    let ref n2 = nodes.get_from_id(node2).unwrap().node_data;
-
-                    let save_path = format!("output/{}/overlap-{}_{}-{}x{}.png",folder,n1.line,n2.line, ss.0, ss.1);
+                    let save_path = format!("output/{}/{}", folder, violation_image_names(test_sets, violation, "png")[0]);
                     println!("savepath: {}", save_path);
                     self.save_to_png(&save_path);
 
@@ -389,7 +504,7 @@ impl<'a,'b> DrawContext<'a,'b> {
         for violation in &violations.all() {
             match violation {
 
-                &LayoutViolation::Overflow { node1, node2, ref overflow_rect, test_set } => {
+                &LayoutViolation::Overflow { node1, node2, ref overflow_rect, test_set, .. } => {
 
                     let test_set = test_sets.sets.get(&test_set).unwrap();
 
@@ -397,7 +512,7 @@ impl<'a,'b> DrawContext<'a,'b> {
                     let ss = test_set.screen_size;
 
 
-                    self.renderer.window_mut().unwrap().set_size(ss.0 as u32, ss.1 as u32);
+                    self.set_size(ss.0 as u32, ss.1 as u32);
 
                     let ref n1 = nodes.get_from_id(node1).unwrap().node_data;
                     let ref n2 = nodes.get_from_id(node2).unwrap().node_data;
@@ -423,10 +538,7 @@ impl<'a,'b> DrawContext<'a,'b> {
                         },
                     }
 
-                    let save_path = format!("output/{}/overflow-L{}_L{}-{}x{}.png",
-                                            folder,
-                                            n1.line,n2.line,
-                                            ss.0, ss.1);
+                    let save_path = format!("output/{}/{}", folder, violation_image_names(test_sets, violation, "png")[0]);
                     println!("savepath: {}", save_path);
                     self.save_to_png(&save_path);
                     self.present();
@@ -436,49 +548,65 @@ impl<'a,'b> DrawContext<'a,'b> {
         }
     }
 
-    pub fn save_alignment_changed_violations(&mut self, test_sets: &TestSets, violations: &LayoutViolations, folder: &str) {
-        for violation in &violations.all() {
-            match violation {
-                &LayoutViolation::AlignmentLost { a: a,b: b, count: count,test_sets: ref violation_test_sets } => {
-
-                    let ts_ = violation_test_sets.get(0).unwrap();
-
-                    let test_set = test_sets.sets.get(&ts_).unwrap();
-
-                    let ref nodes = test_set.nodes;
-                    let ss = test_set.screen_size;
+    /*One panel of an alignment change image, drawn at the camera position: the test set with the
+    pair highlighted, the tab stops of both nodes and a label with the screen size.*/
+    fn draw_alignment_panel(&mut self, test_set: &TestSet, a: NodeSide, b: NodeSide, label: String) {
+        let ref nodes = test_set.nodes;
+        let ss = test_set.screen_size;
 
-                    self.renderer.window_mut().unwrap().set_size((ss.0 as u32) * 2, (ss.1 as u32));
+        self.draw_nodes(&nodes);
+        self.draw_rect(Rect::new(0,0,ss.0 as u32,ss.1 as u32), (0xff,0xff,0xff,0xdd));
 
-                    self.clear();
-                    self.draw_nodes(&nodes);
-                    self.draw_rect(Rect::new(0,0,ss.0 as u32,ss.1 as u32), (0xff,0xff,0xff,0xdd));
+        if let (Some(n1), Some(n2)) = (nodes.get_from_id(a.node), nodes.get_from_id(b.node)) {
+            self.draw_node(n1);
+            self.draw_node(n2);
 
-                    let n1 = &nodes.get_from_id(a.node).unwrap();
-                    let n2 = &nodes.get_from_id(b.node).unwrap();
+            if let Some(ts1) = test_set.tab_stops.find_tab_stop_for_node_side(&a) {
+                self.draw_tab_stop_within(&ts1, "node".to_string(), (0,0), ss);
+            }
+            if let Some(ts2) = test_set.tab_stops.find_tab_stop_for_node_side(&b) {
+                self.draw_tab_stop_within(&ts2, "lost alignment to".to_string(), (0,0), ss);
+            }
 
-                    self.draw_node(n1);
-                    self.draw_node(n2);
+            let ref p1_data = n1.node_data;
+            let ref p2_data = n2.node_data;
+            let p1 = (p1_data.actual_position_x + (p1_data.actual_width / 2) + self.camera_pos.0, p1_data.actual_position_y + (p1_data.actual_height / 2) + self.camera_pos.1);
+            let p2 = (p2_data.actual_position_x + (p2_data.actual_width / 2) + self.camera_pos.0, p2_data.actual_position_y + (p2_data.actual_height / 2) + self.camera_pos.1);
+            self.draw_line((0xff,0x00,0x00), p1, p2);
+        }
 
-                    let ts1 = test_set.tab_stops.tab_stop_connected_to_node_side(&a);
-                    let ts2 = test_set.tab_stops.tab_stop_connected_to_node_side(&b);
+        let label_pos = (self.camera_pos.0 + 4, self.camera_pos.1 + 4);
+        self.draw_text(label, (255,255,255,255), label_pos);
+    }
 
-                    self.draw_tab_stop(&ts1, "node".to_string(), (0,0), false);
-                    self.draw_tab_stop(&ts2, "lost alignment to".to_string(), (0,0), false);
+    //draws the pair where it is aligned next to where the alignment is lost
+    pub fn save_alignment_changed_violations(&mut self, test_sets: &TestSets, violations: &LayoutViolations, screen_size_names: &Vec<(TestSetId, String)>, folder: &str) {
+        for violation in &violations.all() {
+            match violation {
+                &LayoutViolation::AlignmentLost { a, b, .. } => {
+                    let panels = alignment_lost_test_sets(test_sets, violation);
+                    if panels.len() == 0 {
+                        continue;
+                    }
 
-                    let ref p1_data = n1.node_data;
-                    let ref p2_data = n2.node_data;
-                    let p1 = (p1_data.actual_position_x + (p1_data.actual_width / 2), p1_data.actual_position_y + (p1_data.actual_height / 2));
-                    let p2 = (p2_data.actual_position_x + (p2_data.actual_width / 2), p2_data.actual_position_y + (p2_data.actual_height / 2));
-                    self.draw_line((0xff,0x00,0x00), p1, p2);
+                    let width = panels.iter().map(|&(ts, _)| ts.screen_size.0).sum::<i32>();
+                    let height = panels.iter().map(|&(ts, _)| ts.screen_size.1).max().unwrap();
+                    self.set_size(width as u32, height as u32);
+                    self.clear();
 
-                    let save_path = format!("output/{}/alignment_change--A{}-B{}--{}x{}--L{}_L{}.png",
-                                            folder,
-                                            a.node, b.node,
-                                            ss.0, ss.1,
-                                            p1_data.line,p2_data.line);
+                    let mut x = 0;
+                    for &(test_set, shows) in &panels {
+                        if x > 0 {
+                            self.draw_line((0x00,0x00,0x00), (x - 1, 0), (x - 1, height));
+                        }
+                        self.set_camera((x, 0));
+                        let label = format!("{} - {}", screen_size_label(test_set, screen_size_names), shows);
+                        self.draw_alignment_panel(test_set, a, b, label);
+                        x += test_set.screen_size.0;
+                    }
+                    self.set_camera((0, 0));
 
-                    let save_path = save_path.replace(":","-");
+                    let save_path = format!("output/{}/{}", folder, violation_image_names(test_sets, violation, "png")[0]);
 
                     //TODO: this is not the best place to do this check, but it should be ok for now
 
@@ -493,4 +621,42 @@ impl<'a,'b> DrawContext<'a,'b> {
             }
         }
     }
+
+    //draws the violations of rules that are not built in, once for each test set they were found in
+    pub fn save_custom_violations(&mut self, test_sets: &TestSets, violations: &LayoutViolations, folder: &str) {
+        for violation in &violations.all() {
+            match violation {
+                &LayoutViolation::Custom(ref custom) => {
+                    let image_names = violation_image_names(test_sets, violation, "png");
+                    for (ts, image_name) in custom.test_sets.iter().zip(image_names.iter()) {
+                        let test_set = test_sets.sets.get(ts).unwrap();
+
+                        let ref nodes = test_set.nodes;
+                        let ss = test_set.screen_size;
+
+                        self.set_size(ss.0 as u32, ss.1 as u32);
+
+                        self.clear();
+                        self.draw_nodes(&nodes);
+                        self.draw_rect(Rect::new(0,0,ss.0 as u32,ss.1 as u32), (0xff,0xff,0xff,0xdd));
+
+                        for node in &custom.nodes {
+                            if let Some(n) = nodes.get_from_id(*node) {
+                                self.draw_node(&n);
+                            }
+                        }
+                        for r in &custom.rects {
+                            self.draw_grainy_rect(*r);
+                        }
+
+                        let save_path = format!("output/{}/{}", folder, image_name);
+                        println!("savepath: {}", save_path);
+                        self.save_to_png(&save_path);
+                        self.present();
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
 }
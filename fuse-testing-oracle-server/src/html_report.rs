@@ -0,0 +1,188 @@
+use std::fmt::Write;
+use std::collections::HashMap;
+use test_sets::*;
+use layout_validator::validity_rules::{LayoutViolation,LayoutViolations};
+use server::{ServerError,LayoutMismatch};
+use test_runner::AppCrash;
+use drawing::violation_image_names;
+use junit_report::escape_xml;
+use svg::{svg_nodes,svg_violation};
+
+const STYLE: &'static str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+h3 { margin-bottom: 0.2em; }
+h4 { margin: 0.6em 0 0.2em 0; font-family: monospace; }
+ul { margin: 0; }
+.counts td { padding: 0 1em 0 0; }
+.problems { color: #a00; }
+.screen-sizes { display: flex; flex-wrap: wrap; }
+figure { margin: 0 1em 1em 0; }
+svg { width: 320px; border: 1px solid #888; background: #fff; }
+.node { fill: none; stroke: #bbb; stroke-width: 1; }
+.overlay .highlight { fill-opacity: 0.3; }
+";
+
+//shows and hides the overlays of a violation, or of all the violations of a kind
+const SCRIPT: &'static str = "
+function toggleViolation(checkbox) {
+    var overlays = document.querySelectorAll('g[data-violation=\"' + checkbox.getAttribute('data-violation') + '\"]');
+    for (var i = 0; i < overlays.length; i++) {
+        overlays[i].style.display = checkbox.checked ? '' : 'none';
+    }
+}
+function toggleKind(checkbox) {
+    var checkboxes = document.querySelectorAll('input[data-violation][data-kind=\"' + checkbox.getAttribute('data-kind') + '\"]');
+    for (var i = 0; i < checkboxes.length; i++) {
+        checkboxes[i].checked = checkbox.checked;
+        toggleViolation(checkboxes[i]);
+    }
+}
+";
+
+//what is drawn over the node boxes of a test set for a violation, hidden until its checkbox is checked
+fn svg_overlay(out: &mut String, test_set: &TestSet, violation: &LayoutViolation, index: usize) {
+    write!(out, "<g class=\"overlay\" data-violation=\"{}\" style=\"display:none\">", index);
+    svg_violation(out, test_set, violation);
+    write!(out, "</g>");
+}
+
+fn svg_test_set(out: &mut String, test_set: &TestSet, overlays: &Vec<(usize, &LayoutViolation)>, image_extension: Option<&str>) {
+    let (w, h) = test_set.screen_size;
+    writeln!(out, "<figure><figcaption>{}x{} (test set {})</figcaption>", w, h, test_set.id);
+    write!(out, "<svg viewBox=\"0 0 {} {}\" xmlns=\"http://www.w3.org/2000/svg\">", w, h);
+    if let Some(image_extension) = image_extension {
+        //saved next to the report, by run_all_tests or save_svg_test_sets
+        write!(out, "<image href=\"size{}x{}.{}\" x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" opacity=\"0.5\"/>", w, h, image_extension, w, h);
+    }
+    svg_nodes(out, test_set);
+    for &(index, violation) in overlays {
+        svg_overlay(out, test_set, violation, index);
+    }
+    writeln!(out, "</svg></figure>");
+}
+
+//the file and line the violation is listed under, that of its first node
+fn source_line(test_sets: &TestSets, violation: &LayoutViolation) -> String {
+    let node = violation.nodes().into_iter().next().and_then(|node| {
+        violation.test_sets().iter()
+            .filter_map(|id| test_sets.sets.get(id))
+            .filter_map(|ts| ts.nodes.get_from_id(node))
+            .next()
+            .cloned()
+    });
+    match node {
+        Some(node) => format!("{}:{}", node.node_data.file, node.node_data.line),
+        None => "unknown".to_string(),
+    }
+}
+
+/*One html file with everything about a run. The violations are listed by kind and source line,
+and each can be drawn over the node boxes of the screen sizes where it shows up. image_extension
+is the format of the screen size and violation images saved next to it, if they were.*/
+pub fn generate_html_report(name: &str,
+                            test_sets: Option<&TestSets>,
+                            violations: Option<&LayoutViolations>,
+                            failures: &Vec<(TestSetId, String, ServerError)>,
+                            mismatches: &Vec<LayoutMismatch>,
+                            crashes: &Vec<AppCrash>,
+                            image_extension: Option<&str>) -> String {
+    let mut body = String::new();
+    writeln!(body, "<h1>{}</h1>", escape_xml(name));
+
+    if crashes.len() > 0 || failures.len() > 0 || mismatches.len() > 0 {
+        writeln!(body, "<h2>Problems</h2><ul class=\"problems\">");
+        for crash in crashes {
+            writeln!(body, "<li>Crashed - {}</li>", escape_xml(&format!("{}", crash)));
+        }
+        for &(_, ref what, ref error) in failures {
+            writeln!(body, "<li>Failed - {} => {}</li>", escape_xml(what), escape_xml(&format!("{}", error)));
+        }
+        for mismatch in mismatches {
+            writeln!(body, "<li>Discarded - {}</li>", escape_xml(&format!("{}", mismatch)));
+        }
+        writeln!(body, "</ul>");
+    }
+
+    if let (Some(test_sets), Some(violations)) = (test_sets, violations) {
+        let reported = violations.all();
+
+        writeln!(body, "<table class=\"counts\">");
+        writeln!(body, "<tr><td>Test sets</td><td>{}</td></tr>", test_sets.sets.len());
+        writeln!(body, "<tr><td>Reported</td><td>{}</td></tr>", reported.len());
+        writeln!(body, "<tr><td>Baseline</td><td>{}</td></tr>", violations.baselined.len());
+        writeln!(body, "<tr><td>Suppressed</td><td>{}</td></tr>", violations.suppressed.len());
+        writeln!(body, "</table>");
+
+        //kind -> source line -> violations, kinds and lines in the order they first show up
+        let mut kinds: Vec<(String, Vec<(String, Vec<usize>)>)> = Vec::new();
+        for (index, v) in reported.iter().enumerate() {
+            let line = source_line(test_sets, v);
+            let kind_index = match kinds.iter().position(|&(ref kind, _)| kind == v.kind()) {
+                Some(i) => i,
+                None => { kinds.push((v.kind().to_string(), Vec::new())); kinds.len() - 1 },
+            };
+            let ref mut lines = kinds[kind_index].1;
+            match lines.iter().position(|&(ref l, _)| *l == line) {
+                Some(i) => lines[i].1.push(index),
+                None => lines.push((line, vec![index])),
+            }
+        }
+
+        writeln!(body, "<h2>Violations</h2>");
+        for &(ref kind, ref lines) in &kinds {
+            let count: usize = lines.iter().map(|&(_, ref v)| v.len()).sum();
+            writeln!(body, "<h3>{} ({}) <label><input type=\"checkbox\" data-kind=\"{}\" onchange=\"toggleKind(this)\"> show all</label></h3>",
+                     escape_xml(kind), count, escape_xml(kind));
+            for &(ref line, ref indices) in lines {
+                writeln!(body, "<h4>{}</h4><ul>", escape_xml(line));
+                for &index in indices {
+                    let v = &reported[index];
+                    let failing = v.failing_test_sets(test_sets);
+                    let description = match failing.get(0).or(v.test_sets().get(0)).and_then(|id| test_sets.sets.get(id)) {
+                        Some(ts) => v.describe(ts),
+                        None => format!("{}", v),
+                    };
+                    let sizes: Vec<String> = failing.iter()
+                        .filter_map(|id| test_sets.sets.get(id))
+                        .map(|ts| format!("{}x{}", ts.screen_size.0, ts.screen_size.1))
+                        .collect();
+                    write!(body, "<li><label><input type=\"checkbox\" data-violation=\"{}\" data-kind=\"{}\" onchange=\"toggleViolation(this)\"> {}</label> on {}",
+                           index, escape_xml(kind), escape_xml(&description), sizes.join(", "));
+                    if let Some(image_extension) = image_extension {
+                        for image in violation_image_names(test_sets, v, image_extension) {
+                            write!(body, " <a href=\"{}\">image</a>", escape_xml(&image));
+                        }
+                    }
+                    writeln!(body, "</li>");
+                }
+                writeln!(body, "</ul>");
+            }
+        }
+
+        //only the screen sizes with violations are drawn
+        let mut overlays: HashMap<TestSetId, Vec<(usize, &LayoutViolation)>> = HashMap::new();
+        for (index, v) in reported.iter().enumerate() {
+            for id in v.failing_test_sets(test_sets) {
+                overlays.entry(id).or_insert(Vec::new()).push((index, v));
+            }
+        }
+        let mut ids: Vec<&TestSetId> = overlays.keys().collect();
+        ids.sort_by_key(|id| id.0);
+
+        writeln!(body, "<h2>Screen sizes</h2><div class=\"screen-sizes\">");
+        for id in ids {
+            if let Some(test_set) = test_sets.sets.get(id) {
+                svg_test_set(&mut body, test_set, overlays.get(id).unwrap(), image_extension);
+            }
+        }
+        writeln!(body, "</div>");
+    }
+
+    let mut ret = String::new();
+    writeln!(ret, "<!DOCTYPE html>");
+    writeln!(ret, "<html><head><meta charset=\"utf-8\"><title>Layout report - {}</title>", escape_xml(name));
+    writeln!(ret, "<style>{}</style></head><body>", STYLE);
+    ret.push_str(&body);
+    writeln!(ret, "<script>{}</script></body></html>", SCRIPT);
+    ret
+}
@@ -0,0 +1,235 @@
+use serde_json;
+use std::collections::BTreeMap;
+use test_sets::*;
+use layout_validator::validity_rules::{LayoutViolation,LayoutViolations};
+use server::{ServerError,LayoutMismatch};
+use test_runner::AppCrash;
+use drawing::violation_image_names;
+
+#[derive(Serialize)]
+pub struct JsonNode {
+    #[serde(rename = "Id")] pub id: i32,
+    #[serde(rename = "Name")] pub name: String,
+    #[serde(rename = "File")] pub file: String,
+    #[serde(rename = "Line")] pub line: i32,
+}
+
+#[derive(Serialize)]
+pub struct JsonTestSet {
+    #[serde(rename = "Id")] pub id: TestSetId,
+    #[serde(rename = "Width")] pub width: i32,
+    #[serde(rename = "Height")] pub height: i32,
+}
+
+#[derive(Serialize)]
+pub struct JsonViolation<'a> {
+    #[serde(rename = "Violation")] pub violation: &'a LayoutViolation,
+    //Reported, Baseline or Suppressed
+    #[serde(rename = "Status")] pub status: &'static str,
+    #[serde(rename = "Nodes")] pub nodes: Vec<JsonNode>,
+    #[serde(rename = "ScreenSizes")] pub screen_sizes: Vec<JsonTestSet>,
+    //relative to the directory of the report
+    #[serde(rename = "Images")] pub images: Vec<String>,
+    //the suppression that matched a Suppressed violation, with its reason
+    #[serde(rename = "Suppression", skip_serializing_if = "Option::is_none")] pub suppression: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct JsonCounts {
+    #[serde(rename = "Reported")] pub reported: usize,
+    #[serde(rename = "Baseline")] pub baseline: usize,
+    #[serde(rename = "Suppressed")] pub suppressed: usize,
+    //of the reported violations
+    #[serde(rename = "ByKind")] pub by_kind: BTreeMap<String, usize>,
+    #[serde(rename = "BySeverity")] pub by_severity: BTreeMap<String, usize>,
+}
+
+#[derive(Serialize)]
+pub struct JsonFailure {
+    #[serde(rename = "What")] pub what: String,
+    #[serde(rename = "Error")] pub error: String,
+}
+
+#[derive(Serialize)]
+pub struct JsonReport<'a> {
+    #[serde(rename = "Name")] pub name: String,
+    #[serde(rename = "TestSets")] pub test_sets: Vec<JsonTestSet>,
+    #[serde(rename = "Counts")] pub counts: JsonCounts,
+    #[serde(rename = "Violations")] pub violations: Vec<JsonViolation<'a>>,
+    #[serde(rename = "Failures")] pub failures: Vec<JsonFailure>,
+    #[serde(rename = "Crashes")] pub crashes: Vec<String>,
+    #[serde(rename = "Discarded")] pub discarded: Vec<String>,
+}
+
+fn json_test_set(test_set: &TestSet) -> JsonTestSet {
+    JsonTestSet {
+        id: test_set.id,
+        width: test_set.screen_size.0,
+        height: test_set.screen_size.1,
+    }
+}
+
+fn json_violation<'a>(test_sets: &TestSets, violation: &'a LayoutViolation, status: &'static str, image_extension: Option<&str>) -> JsonViolation<'a> {
+    let violation_test_sets: Vec<&TestSet> = violation.test_sets().iter()
+        .filter_map(|id| test_sets.sets.get(id))
+        .collect();
+
+    //a node is the same in all the test sets, so the first one that has it is used
+    let nodes = violation.nodes().iter().filter_map(|id| {
+        violation_test_sets.iter().filter_map(|ts| ts.nodes.get_from_id(*id)).next()
+    }).map(|node| {
+        JsonNode {
+            id: node.id,
+            name: node.node_data.name.clone(),
+            file: node.node_data.file.clone(),
+            line: node.node_data.line,
+        }
+    }).collect();
+
+    JsonViolation {
+        violation: violation,
+        status: status,
+        nodes: nodes,
+        screen_sizes: violation_test_sets.iter().map(|ts| json_test_set(ts)).collect(),
+        images: image_extension.map_or(Vec::new(), |extension| violation_image_names(test_sets, violation, extension)),
+        suppression: None,
+    }
+}
+
+/*The report as json, for other tools to read. Baselined and suppressed violations are listed
+too, with their Status. image_extension is the format of the violation images saved next to it, if they were.*/
+pub fn generate_json_report(name: &str,
+                            test_sets: Option<&TestSets>,
+                            violations: Option<&LayoutViolations>,
+                            failures: &Vec<(TestSetId, String, ServerError)>,
+                            mismatches: &Vec<LayoutMismatch>,
+                            crashes: &Vec<AppCrash>,
+                            image_extension: Option<&str>) -> String {
+    let mut json_test_sets = Vec::new();
+    let mut json_violations = Vec::new();
+    let mut counts = JsonCounts {
+        reported: 0,
+        baseline: 0,
+        suppressed: 0,
+        by_kind: BTreeMap::new(),
+        by_severity: BTreeMap::new(),
+    };
+
+    if let (Some(test_sets), Some(violations)) = (test_sets, violations) {
+        json_test_sets = test_sets.sorted().iter().map(|ts| json_test_set(ts)).collect();
+
+        let reported = violations.all();
+        for v in &reported {
+            *counts.by_kind.entry(v.kind().to_string()).or_insert(0) += 1;
+            *counts.by_severity.entry(format!("{}", v.severity())).or_insert(0) += 1;
+        }
+        counts.reported = reported.len();
+        counts.baseline = violations.baselined.len();
+        counts.suppressed = violations.suppressed.len();
+
+        let all_violations = violations.overflows.iter()
+            .chain(violations.overlaps.iter())
+            .chain(violations.alignment_changes.iter())
+            .chain(violations.unstable_layouts.iter())
+            .chain(violations.custom.iter());
+        for v in all_violations {
+            json_violations.push(json_violation(test_sets, v, "Reported", image_extension));
+        }
+        for v in &violations.baselined {
+            json_violations.push(json_violation(test_sets, v, "Baseline", None));
+        }
+        for &(ref v, ref suppression) in &violations.suppressed {
+            let mut json_violation = json_violation(test_sets, v, "Suppressed", None);
+            json_violation.suppression = Some(format!("{}", suppression));
+            json_violations.push(json_violation);
+        }
+    }
+
+    let report = JsonReport {
+        name: name.to_string(),
+        test_sets: json_test_sets,
+        counts: counts,
+        violations: json_violations,
+        failures: failures.iter().map(|&(_, ref what, ref error)| {
+            JsonFailure {
+                what: what.clone(),
+                error: format!("{}", error),
+            }
+        }).collect(),
+        crashes: crashes.iter().map(|c| format!("{}", c)).collect(),
+        discarded: mismatches.iter().map(|m| format!("{}", m)).collect(),
+    };
+
+    serde_json::to_string_pretty(&report).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use server::{JSONNode,LayoutChanged,ScreenSize};
+    use layout_validator::validate_layout;
+    use layout_validator::validity_rules::CustomViolation;
+    use layout_validator::rules::Severity;
+    use suppressions::{Suppression,remove_suppressed_violations};
+
+    fn test_sets(children: Vec<JSONNode>) -> TestSets {
+        let root = JSONNode::new("Root", "MainView.ux", 1, (0, 0, 300, 600), children);
+        let nodes = LayoutChanged::new(0, ScreenSize::new(300.0, 600.0), root).to_nodes();
+        generate_test_sets(vec![(TestSetId(0), nodes, (300, 600))], HashMap::new(), 0.0)
+    }
+
+    #[test]
+    fn suppressed_violations_show_their_suppression() {
+        let test_sets = test_sets(vec![
+            JSONNode::new("Avatar", "MainView.ux", 2, (0, 0, 100, 100), vec![]),
+            JSONNode::new("Badge", "MainView.ux", 3, (60, 60, 80, 80), vec![]),
+        ]);
+        let mut violations = validate_layout(&test_sets);
+        let suppression = Suppression {
+            kind: Some("Overlap".to_string()),
+            file: None,
+            line: None,
+            element: Some("Badge".to_string()),
+            reason: Some("the badge sits on the avatar".to_string()),
+        };
+        remove_suppressed_violations(&mut violations, &test_sets, &vec![suppression]);
+        assert!(violations.suppressed.len() > 0);
+
+        let json_report = generate_json_report("suppressed", Some(&test_sets), Some(&violations), &Vec::new(), &Vec::new(), &Vec::new(), None);
+        assert!(json_report.contains("\"Status\": \"Suppressed\""), "{}", json_report);
+        assert!(json_report.contains("\"Suppression\": \"kind Overlap, element Badge (the badge sits on the avatar)\""), "{}", json_report);
+        assert!(json_report.contains(&format!("\"Suppressed\": {}", violations.suppressed.len())), "{}", json_report);
+    }
+
+    fn custom(kind: &str, severity: Severity) -> LayoutViolation {
+        LayoutViolation::Custom(CustomViolation {
+            kind: kind.to_string(),
+            test_sets: vec![TestSetId(0)],
+            nodes: vec![0],
+            rects: Vec::new(),
+            message: "Root is wrong".to_string(),
+            severity: severity,
+        })
+    }
+
+    #[test]
+    fn counts_by_kind_and_severity() {
+        let test_sets = test_sets(Vec::new());
+        let mut violations = LayoutViolations::new();
+        violations.add(custom("TapTarget", Severity::Error));
+        violations.add(custom("TapTarget", Severity::Warning));
+        violations.add(custom("Contrast", Severity::Warning));
+        violations.baselined.push(custom("Contrast", Severity::Info));
+
+        let json_report = generate_json_report("counts", Some(&test_sets), Some(&violations), &Vec::new(), &Vec::new(), &Vec::new(), None);
+        assert!(json_report.contains("\"Reported\": 3"), "{}", json_report);
+        assert!(json_report.contains("\"Baseline\": 1"), "{}", json_report);
+        assert!(json_report.contains("\"TapTarget\": 2"), "{}", json_report);
+        assert!(json_report.contains("\"Contrast\": 1"), "{}", json_report);
+        //the severities are counted under the lowercase names --fail-on takes, baselined violations are not counted
+        assert!(json_report.contains("\"error\": 1"), "{}", json_report);
+        assert!(json_report.contains("\"warning\": 2"), "{}", json_report);
+        assert!(!json_report.contains("\"info\""), "{}", json_report);
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt::Write;
+use test_sets::*;
+use layout_validator::validity_rules::{LayoutViolation,LayoutViolations};
+use server::ServerError;
+use test_runner::AppCrash;
+use drawing::violation_image_names;
+
+pub fn escape_xml(s: &str) -> String {
+    s.replace("&", "&amp;")
+        .replace("<", "&lt;")
+        .replace(">", "&gt;")
+        .replace("\"", "&quot;")
+        .replace("'", "&apos;")
+}
+
+/*A <testsuite> for one project, with a <testcase> for each screen size. The reported violations
+of a screen size make its case fail, and the screen sizes that could not be captured are errors.
+images is the run's directory inside output/ and the extension of the violation images, when they were saved.*/
+pub fn generate_junit_test_suite(project_name: &str,
+                                 screen_sizes: &Vec<(TestSetId, String)>,
+                                 test_sets: Option<&TestSets>,
+                                 violations: Option<&LayoutViolations>,
+                                 failures: &Vec<(TestSetId, String, ServerError)>,
+                                 crashes: &Vec<AppCrash>,
+                                 images: Option<(&str, &str)>) -> String {
+    let reported = violations.map_or(Vec::new(), |v| v.all());
+
+    let mut cases = String::new();
+    let mut n_failures = 0;
+    let mut n_errors = 0;
+    for &(id, ref name) in screen_sizes {
+        writeln!(cases, "    <testcase name=\"{}\" classname=\"{}\">", escape_xml(name), escape_xml(project_name));
+
+        let test_set = test_sets.and_then(|t| t.sets.get(&id));
+        //screen sizes can share a name, so they are told apart by the id of their test set
+        let failure = failures.iter().find(|&&(failed_id, _, _)| failed_id == id);
+        match (test_set, failure) {
+            (_, Some(&(_, _, ref error))) => {
+                n_errors += 1;
+                writeln!(cases, "      <error message=\"{}\" type=\"CaptureFailed\"/>", escape_xml(&format!("{}", error)));
+            },
+            (None, None) => {
+                n_errors += 1;
+                writeln!(cases, "      <error message=\"no layout was captured\" type=\"CaptureFailed\"/>");
+            },
+            (Some(test_set), None) => {
+                let test_sets = test_sets.unwrap();
+                let case_violations: Vec<&LayoutViolation> = reported.iter()
+                    .filter(|v| v.failing_test_sets(test_sets).contains(&id))
+                    .collect();
+                if case_violations.len() > 0 {
+                    n_failures += 1;
+                    let lines: Vec<String> = case_violations.iter().map(|v| v.describe(test_set)).collect();
+                    writeln!(cases, "      <failure message=\"{} layout violations\" type=\"LayoutViolation\">{}</failure>",
+                             case_violations.len(), escape_xml(&lines.join("\n")));
+
+                    //the images of this screen size, in the attachment format Jenkins understands
+                    if let Some((image_directory, image_extension)) = images {
+                        let mut attachments = Vec::new();
+                        for v in &case_violations {
+                            let names = violation_image_names(test_sets, v, image_extension);
+                            let v_test_sets = v.test_sets();
+                            for (i, image_name) in names.iter().enumerate() {
+                                //custom violations have an image per test set, the others just one
+                                if names.len() == 1 || v_test_sets.get(i) == Some(&id) {
+                                    attachments.push(format!("[[ATTACHMENT|output/{}/{}]]", image_directory, image_name));
+                                }
+                            }
+                        }
+                        if attachments.len() > 0 {
+                            writeln!(cases, "      <system-out>{}</system-out>", escape_xml(&attachments.join("\n")));
+                        }
+                    }
+                }
+            },
+        }
+
+        let case_crashes: Vec<String> = crashes.iter().filter(|c| c.test_set == id).map(|c| format!("{}", c)).collect();
+        if case_crashes.len() > 0 {
+            writeln!(cases, "      <system-err>{}</system-err>", escape_xml(&case_crashes.join("\n")));
+        }
+
+        writeln!(cases, "    </testcase>");
+    }
+
+    let mut ret = String::new();
+    writeln!(ret, "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"{}\">",
+             escape_xml(project_name), screen_sizes.len(), n_failures, n_errors);
+    ret.push_str(&cases);
+    writeln!(ret, "  </testsuite>");
+    ret
+}
+
+//puts the test suites of all the projects in one document
+pub fn generate_junit_report(test_suites: &Vec<String>) -> String {
+    let mut ret = String::new();
+    writeln!(ret, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+    writeln!(ret, "<testsuites>");
+    for test_suite in test_suites {
+        ret.push_str(test_suite);
+    }
+    writeln!(ret, "</testsuites>");
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use server::{JSONNode,LayoutChanged,ScreenSize};
+
+    #[test]
+    fn screen_sizes_with_the_same_name() {
+        let root = JSONNode::new("Root", "MainView.ux", 1, (0, 0, 300, 600), vec![]);
+        let nodes = LayoutChanged::new(0, ScreenSize::new(300.0, 600.0), root).to_nodes();
+        let test_sets = generate_test_sets(vec![(TestSetId(0), nodes, (300, 600))], HashMap::new(), 0.0);
+        let violations = LayoutViolations::new();
+
+        let screen_sizes = vec![(TestSetId(0), "phone".to_string()), (TestSetId(1), "phone".to_string())];
+        let failures = vec![(TestSetId(1), "phone".to_string(), ServerError::ClientDisconnected)];
+        let crashes = vec![AppCrash {
+            test_set: TestSetId(1),
+            screen_size: "phone".to_string(),
+            exit_status: None,
+            error: ServerError::ClientDisconnected,
+        }];
+        let suite = generate_junit_test_suite("app", &screen_sizes, Some(&test_sets), Some(&violations), &failures, &crashes, None);
+
+        //only the second case failed and crashed, the first was captured without violations
+        assert!(suite.contains("tests=\"2\" failures=\"0\" errors=\"1\""), "{}", suite);
+        let cases: Vec<&str> = suite.split("<testcase").skip(1).collect();
+        assert_eq!(cases.len(), 2);
+        assert!(!cases[0].contains("<error") && !cases[0].contains("<system-err>"), "{}", cases[0]);
+        assert!(cases[1].contains("<error") && cases[1].contains("<system-err>"), "{}", cases[1]);
+    }
+}
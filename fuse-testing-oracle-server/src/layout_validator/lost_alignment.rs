@@ -1,4 +1,5 @@
 use super::validity_rules::{LayoutViolation};
+use super::rules::Severity;
 use super::{TabStops,Nodes,TabStop,Node,Orientation};
 use test_runner::*;
 use itertools::*;
@@ -167,6 +168,7 @@ pub fn check_for_lost_alignment(test_sets: &Vec<TestSet>) -> Vec<LayoutViolation
                 b: n.b,
                 count: c,
                 test_sets: node_side_pair_test_sets.get(&n).unwrap().clone(),
+                severity: Severity::Warning,
             });
         }
     }
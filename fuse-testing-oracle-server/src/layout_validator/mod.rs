@@ -9,7 +9,6 @@ use std::cell::RefCell;
 use std::sync::mpsc::Sender;
 use std::fmt::{Display,Formatter,Result,Write};
 use std::cmp::{min,max};
-use super::TestInstruction;
 use super::test_runner::*;
 use std::cmp::Ord;
 
@@ -19,6 +18,9 @@ pub mod overlap_and_overflow;
 use self::overlap_and_overflow::*;
 mod lost_alignment;
 use self::lost_alignment::*;
+pub mod stability;
+pub mod rules;
+use self::rules::RuleRegistry;
 
 use test_sets::*;
 
@@ -287,7 +289,7 @@ pub enum Orientation {
     Vertical = 2,
 }
 
-#[derive(Hash,PartialOrd,Ord,Eq,PartialEq,Debug,Clone,Copy)]
+#[derive(Serialize,Hash,PartialOrd,Ord,Eq,PartialEq,Debug,Clone,Copy)]
 pub enum Side {
     Left,
     Right,
@@ -295,10 +297,10 @@ pub enum Side {
     Bottom,
 }
 
-#[derive(Hash,PartialOrd,Ord,Eq,PartialEq,Debug,Clone,Copy)]
+#[derive(Serialize,Hash,PartialOrd,Ord,Eq,PartialEq,Debug,Clone,Copy)]
 pub struct NodeSide {
-    pub node: i32,
-    pub side: Side,
+    #[serde(rename = "Node")] pub node: i32,
+    #[serde(rename = "Side")] pub side: Side,
 }
 
 impl Display for NodeSide {
@@ -365,6 +367,11 @@ impl PartialEq for TabStop {
 
 impl Eq for TabStop {}
 
+/*How close (in pixels) the mouse has to be for a tab stop to be highlighted in the tab stop view.
+This only affects drawing, how close node sides have to be to count as aligned is the
+Tolerance of the AlignmentLost rule.*/
+pub const TAB_STOP_HIT_DISTANCE: i32 = 15;
+
 impl TabStop {
     pub fn new(pos: i32, o: Orientation) -> TabStop {
         TabStop {
@@ -376,12 +383,12 @@ impl TabStop {
     pub fn hit_test(&self, mouse_pos: (i32,i32)) -> bool {
         match self.orientation {
             Orientation::Horizontal => {
-                if (mouse_pos.1 - self.pos).abs() < 15 {
+                if (mouse_pos.1 - self.pos).abs() < TAB_STOP_HIT_DISTANCE {
                     return true
                 }
             },
             Orientation::Vertical => {
-                if (mouse_pos.0 - self.pos).abs() < 15 {
+                if (mouse_pos.0 - self.pos).abs() < TAB_STOP_HIT_DISTANCE {
                     return true
                 }
             },
@@ -446,6 +453,13 @@ impl TabStops {
         panic!("Did not find tab_stop for node_side, but we should have!")
     }
 
+    //like tab_stop_connected_to_node_side, for node sides that may be missing (like those of unstable nodes)
+    pub fn find_tab_stop_for_node_side(&self, node_side: &NodeSide) -> Option<TabStop> {
+        self.nodes.iter()
+            .find(|&(_, node_sides)| node_sides.contains(node_side))
+            .map(|(tab_stop, _)| tab_stop.clone())
+    }
+
     pub fn tab_stop_equal_by_nodes(&self, other: &TabStops, ts1: &TabStop, ts2: &TabStop) -> bool {
         if ts1.orientation != ts2.orientation {
             return false
@@ -517,3 +531,7 @@ impl TabStops {
 pub fn validate_layout(test_sets: &TestSets) -> LayoutViolations {
     apply_validity_rules(&test_sets)
 }
+
+pub fn validate_layout_with_rules(rules: &RuleRegistry, test_sets: &TestSets) -> LayoutViolations {
+    rules.check(&test_sets)
+}
@@ -3,7 +3,10 @@ use sdl2::rect::Rect;
 use std::collections::HashSet;
 use itertools::{Itertools,Either};
 use super::validity_rules::{LayoutViolation};
+use super::stability::UnstableNodes;
+use super::rules::Severity;
 use std::cmp::{max,min,Ordering};
+use serde::ser::{Serialize,Serializer,SerializeStruct};
 
 use test_sets::*;
 
@@ -46,6 +49,51 @@ impl OverflowRect {
         }
         tot_area
     }
+
+    //the parts that stick out, one rect for a complete overflow
+    pub fn rects(&self) -> Vec<Rect> {
+        match self {
+            &OverflowRect::Partial { left, top, right, bottom } => {
+                vec![left, top, right, bottom].into_iter().filter_map(|r| r).collect()
+            },
+            &OverflowRect::Complete(rect) => vec![rect],
+        }
+    }
+}
+
+//sdl2's Rect is not serializable, this writes it as { X, Y, Width, Height }
+pub struct JsonRect<'a>(pub &'a Rect);
+
+impl<'a> Serialize for JsonRect<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        let mut state = serializer.serialize_struct("Rect", 4)?;
+        state.serialize_field("X", &self.0.x())?;
+        state.serialize_field("Y", &self.0.y())?;
+        state.serialize_field("Width", &self.0.width())?;
+        state.serialize_field("Height", &self.0.height())?;
+        state.end()
+    }
+}
+
+//a partial overflow has a rect (or null) for each side, a complete one is a single rect
+impl Serialize for OverflowRect {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        match self {
+            &OverflowRect::Partial { ref left, ref top, ref right, ref bottom } => {
+                let mut state = serializer.serialize_struct("OverflowRect", 4)?;
+                state.serialize_field("Left", &left.as_ref().map(JsonRect))?;
+                state.serialize_field("Top", &top.as_ref().map(JsonRect))?;
+                state.serialize_field("Right", &right.as_ref().map(JsonRect))?;
+                state.serialize_field("Bottom", &bottom.as_ref().map(JsonRect))?;
+                state.end()
+            },
+            &OverflowRect::Complete(ref rect) => {
+                let mut state = serializer.serialize_struct("OverflowRect", 1)?;
+                state.serialize_field("Complete", &JsonRect(rect))?;
+                state.end()
+            },
+        }
+    }
 }
 
 pub struct OverflowResult {
@@ -171,41 +219,54 @@ fn check_overflow(nodes: &Nodes, n1: &Node, n2: &Node) -> Option<OverflowResult>
     None
 }
 
-pub fn check_for_overlap_and_overflow(tab_stops: &TabStops, nodes: &Nodes, test_set: &TestSetId) -> Vec<LayoutViolation> {
+//sibling nodes that partly cover each other
+pub fn check_for_overlap(nodes: &Nodes, test_set: &TestSetId, unstable_nodes: &UnstableNodes) -> Vec<LayoutViolation> {
     let mut violations = Vec::new();
 
     for (id1, n1) in &nodes.nodes {
         for (id2, n2) in &nodes.nodes {
-            if id1 == id2 {
+            if id1 == id2 || unstable_nodes.contains_key(id1) || unstable_nodes.contains_key(id2) {
                 continue;
-            } else {
-
-                if let Some(overlap_result) = check_overlap(nodes, n1, n2) {
-                    violations.push(LayoutViolation::Overlap {
-                        test_set: test_set.clone(),
-                        node1: *id1,
-                        node2: *id2,
-                        intersection_rect: overlap_result.intersection_rect,
-                    });
-
-                    /*println!("We have bad layout: {}, {}", id1,id2);
-                    println!("N1:({},{}), N2:({},{}) -- IR:({},{})",
-                             n1.node_data.actual_width, n1.node_data.actual_height,
-                             n2.node_data.actual_width, n2.node_data.actual_height,
-                             overlap_result.intersection_rect.width(),
-                             overlap_result.intersection_rect.height());*/
-
-                }
-                if let Some(overflow_result) = check_overflow(nodes, n1, n2) {
-                    violations.push(LayoutViolation::Overflow {
-                        test_set: test_set.clone(),
-                        node1: *id1,
-                        node2: *id2,
-                        overflow_rect: overflow_result.overflow_rect,
-                    });
-                }
+            }
+            if let Some(overlap_result) = check_overlap(nodes, n1, n2) {
+                violations.push(LayoutViolation::Overlap {
+                    test_set: test_set.clone(),
+                    node1: *id1,
+                    node2: *id2,
+                    intersection_rect: overlap_result.intersection_rect,
+                    severity: Severity::Error,
+                });
+
+                /*println!("We have bad layout: {}, {}", id1,id2);
+                println!("N1:({},{}), N2:({},{}) -- IR:({},{})",
+                         n1.node_data.actual_width, n1.node_data.actual_height,
+                         n2.node_data.actual_width, n2.node_data.actual_height,
+                         overlap_result.intersection_rect.width(),
+                         overlap_result.intersection_rect.height());*/
+            }
+        }
+    }
+
+    violations
+}
 
+//children that stick out of their parent
+pub fn check_for_overflow(nodes: &Nodes, test_set: &TestSetId, unstable_nodes: &UnstableNodes) -> Vec<LayoutViolation> {
+    let mut violations = Vec::new();
 
+    for (id1, n1) in &nodes.nodes {
+        for (id2, n2) in &nodes.nodes {
+            if id1 == id2 || unstable_nodes.contains_key(id1) || unstable_nodes.contains_key(id2) {
+                continue;
+            }
+            if let Some(overflow_result) = check_overflow(nodes, n1, n2) {
+                violations.push(LayoutViolation::Overflow {
+                    test_set: test_set.clone(),
+                    node1: *id1,
+                    node2: *id2,
+                    overflow_rect: overflow_result.overflow_rect,
+                    severity: Severity::Error,
+                });
             }
         }
     }
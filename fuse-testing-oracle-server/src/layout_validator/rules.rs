@@ -0,0 +1,392 @@
+use super::validity_rules::{LayoutViolation,LayoutViolations};
+use super::overlap_and_overflow::{check_for_overlap,check_for_overflow};
+use super::lost_alignment::check_for_lost_alignment;
+use test_sets::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+//ordered from least to most severe
+#[derive(Serialize,Deserialize,Debug,Clone,Copy,PartialEq,Eq,PartialOrd,Ord,Hash)]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Severity::Info => write!(f, "info"),
+            &Severity::Warning => write!(f, "warning"),
+            &Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+//parses the lowercase names used on the command line
+impl FromStr for Severity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Severity, String> {
+        match s {
+            "info" => Ok(Severity::Info),
+            "warning" => Ok(Severity::Warning),
+            "error" => Ok(Severity::Error),
+            _ => Err(format!("{} is not a severity, use error, warning or info", s)),
+        }
+    }
+}
+
+//one entry of the Rules section in the test script, anything left out keeps the rule's default
+#[derive(Deserialize,Debug,Clone,Default)]
+pub struct RuleConfig {
+    #[serde(rename = "Enabled", default)] pub enabled: Option<bool>,
+    #[serde(rename = "Severity", default)] pub severity: Option<Severity>,
+    /*What the rule lets slide. For AlignmentLost it is how many points apart node sides can be
+    and still count as aligned, for Overlap and Overflow the smallest area (in square points) reported.*/
+    #[serde(rename = "Tolerance", default)] pub tolerance: Option<f32>,
+    //share of the test sets (0 to 1) a violation has to show up in to be taken as part of the baseline
+    #[serde(rename = "BaselineThreshold", default)] pub baseline_threshold: Option<f32>,
+}
+
+impl RuleConfig {
+    //the settings of other win over ours, where it has them
+    pub fn merged(&self, other: &RuleConfig) -> RuleConfig {
+        RuleConfig {
+            enabled: other.enabled.or(self.enabled),
+            severity: other.severity.or(self.severity),
+            tolerance: other.tolerance.or(self.tolerance),
+            baseline_threshold: other.baseline_threshold.or(self.baseline_threshold),
+        }
+    }
+}
+
+//rule name -> its settings
+pub type RulesConfig = HashMap<String, RuleConfig>;
+
+//the project's rule settings on top of the ones for the whole test script
+pub fn merge_rules_config(rules: &RulesConfig, overrides: &RulesConfig) -> RulesConfig {
+    let mut merged = rules.clone();
+    for (name, rule_config) in overrides {
+        let merged_config = match merged.get(name) {
+            Some(base) => base.merged(rule_config),
+            None => rule_config.clone(),
+        };
+        merged.insert(name.clone(), merged_config);
+    }
+    merged
+}
+
+/*A check run on the captured layouts. Rules that look at one screen size at a time implement
+check_test_set, rules that compare screen sizes implement check_test_sets. The violations a rule
+reports should be of its own kind: built in rules have their own LayoutViolation variant, others
+report LayoutViolation::Custom with the rule's name as the kind.*/
+pub trait LayoutRule: Send + Sync {
+    //the name used in reports and in the test script
+    fn name(&self) -> &str;
+
+    fn default_severity(&self) -> Severity {
+        Severity::Error
+    }
+
+    //called with the rule's entry in the Rules section of the test script, if it has one
+    fn configure(&mut self, _config: &RuleConfig) {
+    }
+
+    //called once for each test set (screen size)
+    fn check_test_set(&self, _test_set: &TestSet) -> Vec<LayoutViolation> {
+        Vec::new()
+    }
+
+    //called once with all the test sets
+    fn check_test_sets(&self, _test_sets: &TestSets) -> Vec<LayoutViolation> {
+        Vec::new()
+    }
+}
+
+pub struct OverlapRule {
+    //smaller overlaps are not reported
+    pub min_area: f32,
+}
+
+impl LayoutRule for OverlapRule {
+    fn name(&self) -> &str { "Overlap" }
+
+    fn configure(&mut self, config: &RuleConfig) {
+        if let Some(tolerance) = config.tolerance {
+            self.min_area = tolerance;
+        }
+    }
+
+    fn check_test_set(&self, test_set: &TestSet) -> Vec<LayoutViolation> {
+        let min_area = self.min_area;
+        check_for_overlap(&test_set.nodes, &test_set.id, &test_set.unstable_nodes).into_iter().filter(|v| {
+            match v {
+                &LayoutViolation::Overlap { intersection_rect, .. } => {
+                    (intersection_rect.width() * intersection_rect.height()) as f32 >= min_area
+                },
+                _ => true,
+            }
+        }).collect()
+    }
+}
+
+pub struct OverflowRule {
+    //smaller overflows are not reported
+    pub min_area: f32,
+}
+
+impl LayoutRule for OverflowRule {
+    fn name(&self) -> &str { "Overflow" }
+
+    fn configure(&mut self, config: &RuleConfig) {
+        if let Some(tolerance) = config.tolerance {
+            self.min_area = tolerance;
+        }
+    }
+
+    fn check_test_set(&self, test_set: &TestSet) -> Vec<LayoutViolation> {
+        let min_area = self.min_area;
+        check_for_overflow(&test_set.nodes, &test_set.id, &test_set.unstable_nodes).into_iter().filter(|v| {
+            match v {
+                &LayoutViolation::Overflow { ref overflow_rect, .. } => overflow_rect.area() >= min_area,
+                _ => true,
+            }
+        }).collect()
+    }
+}
+
+//the tolerance of this rule is used when the test sets are made, see generate_test_sets
+pub struct AlignmentLostRule;
+
+impl LayoutRule for AlignmentLostRule {
+    fn name(&self) -> &str { "AlignmentLost" }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    fn check_test_sets(&self, test_sets: &TestSets) -> Vec<LayoutViolation> {
+        let test_sets_vec = test_sets.sorted().into_iter().cloned().collect();
+        check_for_lost_alignment(&test_sets_vec)
+    }
+}
+
+//reports the nodes that moved between repeated captures, see stability::find_unstable_nodes
+pub struct UnstableLayoutRule;
+
+impl LayoutRule for UnstableLayoutRule {
+    fn name(&self) -> &str { "UnstableLayout" }
+
+    fn default_severity(&self) -> Severity {
+        Severity::Warning
+    }
+
+    fn check_test_set(&self, test_set: &TestSet) -> Vec<LayoutViolation> {
+        let mut nodes: Vec<&i32> = test_set.unstable_nodes.keys().collect();
+        nodes.sort();
+        nodes.into_iter().map(|node| {
+            LayoutViolation::UnstableLayout {
+                test_set: test_set.id,
+                node: *node,
+                rects: test_set.unstable_nodes[node].clone(),
+                severity: Severity::Warning,
+            }
+        }).collect()
+    }
+}
+
+//the rules validation runs, in the order they were registered
+pub struct RuleRegistry {
+    rules: Vec<Box<dyn LayoutRule>>,
+    //rule name -> severity, starts out as the rule's default
+    severities: HashMap<String, Severity>,
+}
+
+impl RuleRegistry {
+    pub fn new() -> RuleRegistry {
+        RuleRegistry {
+            rules: Vec::new(),
+            severities: HashMap::new(),
+        }
+    }
+
+    pub fn with_default_rules() -> RuleRegistry {
+        let mut registry = RuleRegistry::new();
+        registry.register(Box::new(OverlapRule { min_area: 0.0 }));
+        registry.register(Box::new(OverflowRule { min_area: 0.0 }));
+        registry.register(Box::new(AlignmentLostRule));
+        registry.register(Box::new(UnstableLayoutRule));
+        registry
+    }
+
+    //a rule with the same name as one already registered replaces it
+    pub fn register(&mut self, rule: Box<dyn LayoutRule>) {
+        self.severities.insert(rule.name().to_string(), rule.default_severity());
+        match self.rules.iter().position(|r| r.name() == rule.name()) {
+            Some(index) => self.rules[index] = rule,
+            None => self.rules.push(rule),
+        }
+    }
+
+    //applies the Rules section of the test script, disabled rules are removed
+    pub fn configure(&mut self, config: &RulesConfig) {
+        for name in config.keys() {
+            if !self.rules.iter().any(|r| r.name() == name) {
+                println!("The test script configures the rule {}, but there is no such rule", name);
+            }
+        }
+
+        self.rules.retain(|rule| {
+            config.get(rule.name()).and_then(|c| c.enabled).unwrap_or(true)
+        });
+
+        for rule in &mut self.rules {
+            if let Some(rule_config) = config.get(rule.name()) {
+                rule.configure(rule_config);
+                if let Some(severity) = rule_config.severity {
+                    self.severities.insert(rule.name().to_string(), severity);
+                }
+            }
+        }
+    }
+
+    pub fn rules(&self) -> &Vec<Box<dyn LayoutRule>> {
+        &self.rules
+    }
+
+    //the severity of the violations of the given kind, unknown kinds are errors
+    pub fn severity(&self, kind: &str) -> Severity {
+        self.severities.get(kind).cloned().unwrap_or(Severity::Error)
+    }
+
+    pub fn check(&self, test_sets: &TestSets) -> LayoutViolations {
+        let mut violations = LayoutViolations::new();
+
+        for rule in &self.rules {
+            println!("Checking rule {}", rule.name());
+            let severity = self.severity(rule.name());
+            for test_set in test_sets.sorted() {
+                for mut violation in rule.check_test_set(test_set) {
+                    violation.set_severity(severity);
+                    violations.add(violation);
+                }
+            }
+            for mut violation in rule.check_test_sets(test_sets) {
+                violation.set_severity(severity);
+                violations.add(violation);
+            }
+        }
+
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::validity_rules::CustomViolation;
+    use server::{JSONNode,LayoutChanged,ScreenSize};
+
+    //an avatar with a 40x40 corner of a badge drawn over it, on two screen sizes
+    fn avatar_and_badge() -> TestSets {
+        let test_data = (0..2).map(|id| {
+            let root = JSONNode::new("Root", "MainView.ux", 1, (0, 0, 300, 600), vec![
+                JSONNode::new("Avatar", "MainView.ux", 2, (0, 0, 100, 100), vec![]),
+                JSONNode::new("Badge", "MainView.ux", 3, (60, 60, 80, 80), vec![]),
+            ]);
+            (TestSetId(id), LayoutChanged::new(id, ScreenSize::new(300.0, 600.0), root).to_nodes(), (300, 600))
+        }).collect();
+        generate_test_sets(test_data, HashMap::new(), 0.0)
+    }
+
+    fn rules_config(name: &str, rule_config: RuleConfig) -> RulesConfig {
+        let mut config = RulesConfig::new();
+        config.insert(name.to_string(), rule_config);
+        config
+    }
+
+    fn rule_names(registry: &RuleRegistry) -> Vec<&str> {
+        registry.rules().iter().map(|r| r.name()).collect()
+    }
+
+    //reports the root of every test set, to see the order the test sets are checked in
+    struct RootRule;
+
+    impl LayoutRule for RootRule {
+        fn name(&self) -> &str { "Root" }
+
+        fn default_severity(&self) -> Severity {
+            Severity::Info
+        }
+
+        fn check_test_set(&self, test_set: &TestSet) -> Vec<LayoutViolation> {
+            vec![LayoutViolation::Custom(CustomViolation {
+                kind: "Root".to_string(),
+                test_sets: vec![test_set.id],
+                nodes: vec![0],
+                rects: Vec::new(),
+                message: "the root".to_string(),
+                severity: Severity::Error,
+            })]
+        }
+    }
+
+    #[test]
+    fn configure_disables_rules() {
+        let mut registry = RuleRegistry::with_default_rules();
+        assert_eq!(rule_names(&registry), vec!["Overlap", "Overflow", "AlignmentLost", "UnstableLayout"]);
+        assert!(registry.check(&avatar_and_badge()).overlaps.len() > 0);
+
+        registry.configure(&rules_config("Overlap", RuleConfig { enabled: Some(false), ..Default::default() }));
+        assert_eq!(rule_names(&registry), vec!["Overflow", "AlignmentLost", "UnstableLayout"]);
+        assert_eq!(registry.check(&avatar_and_badge()).overlaps.len(), 0);
+
+        //a rule that is left out, or only has other settings, stays enabled
+        let mut registry = RuleRegistry::with_default_rules();
+        registry.configure(&rules_config("Overlap", RuleConfig { enabled: Some(true), ..Default::default() }));
+        registry.configure(&rules_config("Overflow", RuleConfig { severity: Some(Severity::Info), ..Default::default() }));
+        assert_eq!(rule_names(&registry).len(), 4);
+    }
+
+    #[test]
+    fn configure_overrides_severity() {
+        let mut registry = RuleRegistry::with_default_rules();
+        assert_eq!(registry.severity("Overlap"), Severity::Error);
+        assert_eq!(registry.severity("AlignmentLost"), Severity::Warning);
+        //kinds no rule reports are errors
+        assert_eq!(registry.severity("NoSuchRule"), Severity::Error);
+
+        registry.configure(&rules_config("Overlap", RuleConfig { severity: Some(Severity::Info), ..Default::default() }));
+        assert_eq!(registry.severity("Overlap"), Severity::Info);
+        assert_eq!(registry.severity("Overflow"), Severity::Error);
+
+        let violations = registry.check(&avatar_and_badge());
+        assert!(violations.overlaps.len() > 0);
+        assert!(violations.overlaps.iter().all(|v| v.severity() == Severity::Info));
+    }
+
+    #[test]
+    fn configure_sets_tolerance() {
+        let mut registry = RuleRegistry::with_default_rules();
+        registry.configure(&rules_config("Overlap", RuleConfig { tolerance: Some(1600.0), ..Default::default() }));
+        assert!(registry.check(&avatar_and_badge()).overlaps.len() > 0);
+
+        registry.configure(&rules_config("Overlap", RuleConfig { tolerance: Some(1601.0), ..Default::default() }));
+        assert_eq!(registry.check(&avatar_and_badge()).overlaps.len(), 0);
+    }
+
+    #[test]
+    fn registered_rules_are_configured_and_checked_in_test_set_order() {
+        let mut registry = RuleRegistry::new();
+        registry.register(Box::new(RootRule));
+        assert_eq!(registry.severity("Root"), Severity::Info);
+        registry.configure(&rules_config("Root", RuleConfig { severity: Some(Severity::Warning), ..Default::default() }));
+
+        let violations = registry.check(&avatar_and_badge());
+        let test_sets: Vec<Vec<TestSetId>> = violations.custom.iter().map(|v| v.test_sets()).collect();
+        assert_eq!(test_sets, vec![vec![TestSetId(0)], vec![TestSetId(1)]]);
+        assert!(violations.custom.iter().all(|v| v.severity() == Severity::Warning));
+    }
+}
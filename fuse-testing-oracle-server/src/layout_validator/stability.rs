@@ -0,0 +1,113 @@
+use sdl2::rect::Rect;
+use std::collections::{HashMap,HashSet};
+
+use super::{Nodes,Node};
+
+//node id -> where the node was in each capture it appeared in
+pub type UnstableNodes = HashMap<i32, Vec<Rect>>;
+
+fn node_rect(node: &Node) -> Rect {
+    let ref d = node.node_data;
+    Rect::new(d.actual_position_x, d.actual_position_y,
+              d.actual_width as u32, d.actual_height as u32)
+}
+
+fn same_geometry(a: &Node, b: &Node) -> bool {
+    let ref a = a.node_data;
+    let ref b = b.node_data;
+    a.actual_position_x == b.actual_position_x
+        && a.actual_position_y == b.actual_position_y
+        && a.actual_width == b.actual_width
+        && a.actual_height == b.actual_height
+        && a.render_position_x == b.render_position_x
+        && a.render_position_y == b.render_position_y
+        && a.render_width == b.render_width
+        && a.render_height == b.render_height
+}
+
+fn same_node(a: &Node, b: &Node) -> bool {
+    a.node_data.name == b.node_data.name
+        && a.node_data.line == b.node_data.line
+        && a.node_data.file == b.node_data.file
+}
+
+/*Compares captures of the same screen size, taken one after another. Node ids come from the
+order of the crawl, so a node is unstable if it moved or resized between captures, or if its id
+points to a different node (or to nothing) in one of them. Those nodes are most likely still
+animating, so whatever they overlap or align with in a single capture is not to be trusted.*/
+pub fn find_unstable_nodes(captures: &Vec<Nodes>) -> UnstableNodes {
+    let mut unstable = UnstableNodes::new();
+    if captures.len() < 2 {
+        return unstable;
+    }
+
+    let mut ids = HashSet::new();
+    for capture in captures {
+        ids.extend(capture.nodes.keys().cloned());
+    }
+
+    for id in ids {
+        let nodes: Vec<Option<&Node>> = captures.iter().map(|c| c.get_from_id(id)).collect();
+        let first = nodes[0];
+        let stable = nodes.iter().all(|n| match (first, *n) {
+            (Some(first), Some(n)) => same_node(first, n) && same_geometry(first, n),
+            _ => false,
+        });
+        if !stable {
+            let rects = nodes.iter().filter_map(|n| n.map(node_rect)).collect();
+            unstable.insert(id, rects);
+        }
+    }
+
+    unstable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use server::{JSONNode,LayoutChanged,ScreenSize};
+    use test_sets::{generate_test_sets,TestSetId};
+    use layout_validator::validate_layout;
+    use text_report::generate_violations_report;
+
+    fn capture(children: Vec<JSONNode>) -> Nodes {
+        let root = JSONNode::new("Root", "MainView.ux", 1, (0, 0, 300, 600), children);
+        LayoutChanged::new(0, ScreenSize::new(300.0, 600.0), root).to_nodes()
+    }
+
+    #[test]
+    fn moved_nodes_are_unstable() {
+        let first = capture(vec![JSONNode::new("Panel", "MainView.ux", 2, (0, 0, 300, 100), vec![])]);
+        let second = capture(vec![JSONNode::new("Panel", "MainView.ux", 2, (0, 10, 300, 100), vec![])]);
+
+        assert_eq!(find_unstable_nodes(&vec![first.clone()]).len(), 0);
+        assert_eq!(find_unstable_nodes(&vec![first.clone(), first.clone()]).len(), 0);
+
+        let unstable = find_unstable_nodes(&vec![first, second]);
+        assert_eq!(unstable.len(), 1);
+        assert_eq!(unstable[&1], vec![Rect::new(0, 0, 300, 100), Rect::new(0, 10, 300, 100)]);
+    }
+
+    #[test]
+    fn report_unstable_node_missing_from_last_capture() {
+        let first = capture(vec![
+            JSONNode::new("Panel", "MainView.ux", 2, (0, 0, 300, 100), vec![]),
+            JSONNode::new("Toast", "MainView.ux", 3, (0, 500, 300, 100), vec![]),
+        ]);
+        let last = capture(vec![
+            JSONNode::new("Panel", "MainView.ux", 2, (0, 0, 300, 100), vec![]),
+        ]);
+
+        let unstable = find_unstable_nodes(&vec![first, last.clone()]);
+        let missing: Vec<i32> = unstable.keys().cloned().filter(|id| last.get_from_id(*id).is_none()).collect();
+        assert_eq!(missing.len(), 1);
+
+        let mut unstable_nodes = HashMap::new();
+        unstable_nodes.insert(TestSetId(0), unstable);
+        let test_sets = generate_test_sets(vec![(TestSetId(0), last, (300, 600))], unstable_nodes, 0.0);
+        let violations = validate_layout(&test_sets);
+
+        let report = generate_violations_report(&test_sets, &violations);
+        assert!(report.contains(&format!("UnstableLayout (warning) - test-set:0 => node {} changed between captures and was found in 1 of them", missing[0])), "{}", report);
+    }
+}
@@ -5,10 +5,13 @@ use std::hash::{Hash,Hasher};
 use itertools::{Itertools,Either};
 use super::overlap_and_overflow::*;
 use super::lost_alignment::*;
+use super::rules::{RuleRegistry,Severity};
 use test_runner::*;
 use test_sets::*;
+use suppressions::Suppression;
 use super::NodeSide;
 use std::fmt;
+use serde::ser::{Serialize,Serializer,SerializeStruct};
 
 #[derive(Clone)]
 enum OverflowType {
@@ -31,26 +34,134 @@ struct Overflow {
     pub directions: HashSet<Direction>,
 }
 
+/*A violation reported by a rule that is not built in. It carries what the report, the baseline and
+the drawing need to handle it without knowing the rule.*/
+#[derive(Debug,Clone)]
+pub struct CustomViolation {
+    //the name of the rule that reported it
+    pub kind: String,
+    pub test_sets: Vec<TestSetId>,
+    pub nodes: Vec<i32>,
+    //what to highlight when the violation is drawn
+    pub rects: Vec<Rect>,
+    pub message: String,
+    pub severity: Severity,
+}
+
 #[derive(Debug,Clone)]
 pub enum LayoutViolation {
     Overlap {
         test_set: TestSetId,
         node1: i32,
         node2: i32,
-        intersection_rect: Rect
+        intersection_rect: Rect,
+        severity: Severity,
     },
     Overflow {
         test_set: TestSetId,
         node1: i32,
         node2: i32,
-        overflow_rect: OverflowRect
+        overflow_rect: OverflowRect,
+        severity: Severity,
     },
     AlignmentLost {
         a: NodeSide,
         b: NodeSide,
         count: i32,
         test_sets: Vec<TestSetId>,
+        severity: Severity,
+    },
+    //the node moved between repeated captures, rects has where it was in each of them
+    UnstableLayout {
+        test_set: TestSetId,
+        node: i32,
+        rects: Vec<Rect>,
+        severity: Severity,
     },
+    Custom(CustomViolation),
+}
+
+impl LayoutViolation {
+    //the name of the rule that reports this kind of violation
+    pub fn kind(&self) -> &str {
+        match self {
+            &LayoutViolation::Overlap { .. } => "Overlap",
+            &LayoutViolation::Overflow { .. } => "Overflow",
+            &LayoutViolation::AlignmentLost { .. } => "AlignmentLost",
+            &LayoutViolation::UnstableLayout { .. } => "UnstableLayout",
+            &LayoutViolation::Custom(ref custom) => &custom.kind,
+        }
+    }
+
+    pub fn test_sets(&self) -> Vec<TestSetId> {
+        match self {
+            &LayoutViolation::Overlap { test_set, .. } => vec![test_set],
+            &LayoutViolation::Overflow { test_set, .. } => vec![test_set],
+            &LayoutViolation::AlignmentLost { ref test_sets, .. } => test_sets.clone(),
+            &LayoutViolation::UnstableLayout { test_set, .. } => vec![test_set],
+            &LayoutViolation::Custom(ref custom) => custom.test_sets.clone(),
+        }
+    }
+
+    /*The test sets where the layout is wrong. An alignment change is listed with the test sets
+    where the nodes are aligned, so it is wrong in the others.*/
+    pub fn failing_test_sets(&self, all_test_sets: &TestSets) -> Vec<TestSetId> {
+        match self {
+            &LayoutViolation::AlignmentLost { ref test_sets, .. } => {
+                let mut ids: Vec<TestSetId> = all_test_sets.sets.keys().filter(|id| !test_sets.contains(id)).cloned().collect();
+                ids.sort_by_key(|id| id.0);
+                ids
+            },
+            _ => self.test_sets(),
+        }
+    }
+
+    //one line for the reports, like "Overlap (error) - Badge @ Main.ux:12 - Avatar @ Main.ux:10"
+    pub fn describe(&self, test_set: &TestSet) -> String {
+        let mut ret = format!("{} ({})", self.kind(), self.severity());
+        for node in self.nodes() {
+            match test_set.nodes.get_from_id(node) {
+                Some(n) => ret.push_str(&format!(" - {} @ {}:{}", n.node_data.name, n.node_data.file, n.node_data.line)),
+                None => ret.push_str(&format!(" - node {}", node)),
+            }
+        }
+        if let &LayoutViolation::Custom(ref custom) = self {
+            ret.push_str(&format!(": {}", custom.message));
+        }
+        ret
+    }
+
+    //starts out as the default of the rule that reported it, RuleRegistry applies the configured one
+    pub fn severity(&self) -> Severity {
+        match self {
+            &LayoutViolation::Overlap { severity, .. } => severity,
+            &LayoutViolation::Overflow { severity, .. } => severity,
+            &LayoutViolation::AlignmentLost { severity, .. } => severity,
+            &LayoutViolation::UnstableLayout { severity, .. } => severity,
+            &LayoutViolation::Custom(ref custom) => custom.severity,
+        }
+    }
+
+    pub fn set_severity(&mut self, new_severity: Severity) {
+        match self {
+            &mut LayoutViolation::Overlap { ref mut severity, .. } => *severity = new_severity,
+            &mut LayoutViolation::Overflow { ref mut severity, .. } => *severity = new_severity,
+            &mut LayoutViolation::AlignmentLost { ref mut severity, .. } => *severity = new_severity,
+            &mut LayoutViolation::UnstableLayout { ref mut severity, .. } => *severity = new_severity,
+            &mut LayoutViolation::Custom(ref mut custom) => custom.severity = new_severity,
+        }
+    }
+
+    //the nodes involved, the first one is the one the violation is about
+    pub fn nodes(&self) -> Vec<i32> {
+        match self {
+            &LayoutViolation::Overlap { node1, node2, .. } => vec![node1, node2],
+            &LayoutViolation::Overflow { node1, node2, .. } => vec![node2, node1],
+            &LayoutViolation::AlignmentLost { a, b, .. } => vec![a.node, b.node],
+            &LayoutViolation::UnstableLayout { node, .. } => vec![node],
+            &LayoutViolation::Custom(ref custom) => custom.nodes.clone(),
+        }
+    }
 }
 
 impl Hash for LayoutViolation {
@@ -66,6 +177,16 @@ impl Hash for LayoutViolation {
             },
             &LayoutViolation::AlignmentLost { a, b, .. } => {
 
+            },
+            &LayoutViolation::UnstableLayout { test_set, node, .. } => {
+                test_set.hash(state);
+                node.hash(state);
+            },
+            &LayoutViolation::Custom(ref custom) => {
+                custom.kind.hash(state);
+                let mut nodes = custom.nodes.clone();
+                nodes.sort();
+                nodes.hash(state);
             }
         }
     }
@@ -92,6 +213,18 @@ impl PartialEq for LayoutViolation {
              &LayoutViolation::AlignmentLost {a: a_b, b: b_b, .. }) => {
                 a_a == a_b && b_a == b_b
             },
+            (&LayoutViolation::UnstableLayout {test_set: ts_a, node: n_a, .. },
+             &LayoutViolation::UnstableLayout {test_set: ts_b, node: n_b, .. }) => {
+                ts_a == ts_b && n_a == n_b
+            },
+            //like overlaps, the same nodes on another screen size count as the same violation
+            (&LayoutViolation::Custom(ref a), &LayoutViolation::Custom(ref b)) => {
+                let mut nodes_a = a.nodes.clone();
+                let mut nodes_b = b.nodes.clone();
+                nodes_a.sort();
+                nodes_b.sort();
+                a.kind == b.kind && nodes_a == nodes_b
+            },
             _ => {
                 false
             }
@@ -100,12 +233,74 @@ impl PartialEq for LayoutViolation {
 }
 impl Eq for LayoutViolation {}
 
+/*Written as one object with the rule name as Kind, the Severity, and the fields of the variant.
+Node ids refer to the nodes of the test sets, see json_report for a version with file and line.*/
+impl Serialize for LayoutViolation {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        match self {
+            &LayoutViolation::Overlap { test_set, node1, node2, ref intersection_rect, severity } => {
+                let mut state = serializer.serialize_struct("LayoutViolation", 6)?;
+                state.serialize_field("Kind", self.kind())?;
+                state.serialize_field("Severity", &severity)?;
+                state.serialize_field("TestSet", &test_set)?;
+                state.serialize_field("Node1", &node1)?;
+                state.serialize_field("Node2", &node2)?;
+                state.serialize_field("IntersectionRect", &JsonRect(intersection_rect))?;
+                state.end()
+            },
+            &LayoutViolation::Overflow { test_set, node1, node2, ref overflow_rect, severity } => {
+                let mut state = serializer.serialize_struct("LayoutViolation", 6)?;
+                state.serialize_field("Kind", self.kind())?;
+                state.serialize_field("Severity", &severity)?;
+                state.serialize_field("TestSet", &test_set)?;
+                state.serialize_field("Node1", &node1)?;
+                state.serialize_field("Node2", &node2)?;
+                state.serialize_field("OverflowRect", overflow_rect)?;
+                state.end()
+            },
+            &LayoutViolation::AlignmentLost { a, b, count, ref test_sets, severity } => {
+                let mut state = serializer.serialize_struct("LayoutViolation", 6)?;
+                state.serialize_field("Kind", self.kind())?;
+                state.serialize_field("Severity", &severity)?;
+                state.serialize_field("A", &a)?;
+                state.serialize_field("B", &b)?;
+                state.serialize_field("Count", &count)?;
+                state.serialize_field("TestSets", test_sets)?;
+                state.end()
+            },
+            &LayoutViolation::UnstableLayout { test_set, node, ref rects, severity } => {
+                let rects: Vec<JsonRect> = rects.iter().map(JsonRect).collect();
+                let mut state = serializer.serialize_struct("LayoutViolation", 5)?;
+                state.serialize_field("Kind", self.kind())?;
+                state.serialize_field("Severity", &severity)?;
+                state.serialize_field("TestSet", &test_set)?;
+                state.serialize_field("Node", &node)?;
+                state.serialize_field("Rects", &rects)?;
+                state.end()
+            },
+            &LayoutViolation::Custom(ref custom) => {
+                let rects: Vec<JsonRect> = custom.rects.iter().map(JsonRect).collect();
+                let mut state = serializer.serialize_struct("LayoutViolation", 6)?;
+                state.serialize_field("Kind", &custom.kind)?;
+                state.serialize_field("Severity", &custom.severity)?;
+                state.serialize_field("TestSets", &custom.test_sets)?;
+                state.serialize_field("Nodes", &custom.nodes)?;
+                state.serialize_field("Rects", &rects)?;
+                state.serialize_field("Message", &custom.message)?;
+                state.end()
+            },
+        }
+    }
+}
+
 impl fmt::Display for LayoutViolation {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             &LayoutViolation::Overlap { node1, node2, .. } => { write!(f, "Overlap:({}, {})", node1, node2) }
             &LayoutViolation::Overflow { node1, node2, .. } => { write!(f, "Overflow:({}, {})", node1, node2) }
             &LayoutViolation::AlignmentLost { a,b,.. } => { write!(f, "AlignmentChange:({}, {})", a, b) }
+            &LayoutViolation::UnstableLayout { node, .. } => { write!(f, "UnstableLayout:({})", node) }
+            &LayoutViolation::Custom(ref custom) => { write!(f, "{}:({:?})", custom.kind, custom.nodes) }
         }
 
     }
@@ -115,40 +310,57 @@ pub struct LayoutViolations {
     pub overflows: Vec<LayoutViolation>,
     pub overlaps: Vec<LayoutViolation>,
     pub alignment_changes: Vec<LayoutViolation>,
+    pub unstable_layouts: Vec<LayoutViolation>,
+    //everything reported by rules that are not built in
+    pub custom: Vec<LayoutViolation>,
+    //matched a suppression in the test script, counted but not reported. Each with the first suppression it matched
+    pub suppressed: Vec<(LayoutViolation, Suppression)>,
+    //taken as part of the baseline, see baseline_finder
+    pub baselined: Vec<LayoutViolation>,
 }
 
 impl LayoutViolations {
+    pub fn new() -> LayoutViolations {
+        LayoutViolations {
+            overflows: Vec::new(),
+            overlaps: Vec::new(),
+            alignment_changes: Vec::new(),
+            unstable_layouts: Vec::new(),
+            custom: Vec::new(),
+            suppressed: Vec::new(),
+            baselined: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, violation: LayoutViolation) {
+        match violation {
+            LayoutViolation::Overflow { .. } => self.overflows.push(violation),
+            LayoutViolation::Overlap { .. } => self.overlaps.push(violation),
+            LayoutViolation::AlignmentLost { .. } => self.alignment_changes.push(violation),
+            LayoutViolation::UnstableLayout { .. } => self.unstable_layouts.push(violation),
+            LayoutViolation::Custom(_) => self.custom.push(violation),
+        }
+    }
+
+    //the reported violations, without the suppressed ones
     pub fn all(&self) -> Vec<LayoutViolation> {
         let mut ret = Vec::new();
         for lv in &self.overflows { ret.push(lv.clone()); }
         for lv in &self.overlaps { ret.push(lv.clone()); }
         for lv in &self.alignment_changes { ret.push(lv.clone()); }
+        for lv in &self.unstable_layouts { ret.push(lv.clone()); }
+        for lv in &self.custom { ret.push(lv.clone()); }
         ret
     }
+
+    //how many of the reported violations are at least this severe
+    pub fn count_at_least(&self, severity: Severity) -> usize {
+        self.all().iter().filter(|v| v.severity() >= severity).count()
+    }
 }
 
+//runs the built in rules, see RuleRegistry for adding more
 pub fn apply_validity_rules(test_sets: &TestSets) -> LayoutViolations {
     println!("we are testing layout for validity");
-    let mut overlaps = Vec::new();
-    let mut overflows = Vec::new();
-
-    for (_, test_set) in test_sets.sets.iter() {
-        let mut overflow_and_overlap_result = check_for_overlap_and_overflow(&test_set.tab_stops, &test_set.nodes, &test_set.id);
-        for violation in overflow_and_overlap_result {
-            match violation {
-                LayoutViolation::Overflow { .. } => { overflows.push(violation.clone()) },
-                LayoutViolation::Overlap { .. } => { overlaps.push(violation.clone()) },
-                LayoutViolation::AlignmentLost { .. } => panic!("This collection should not contain any violations of this type (alignment change)")
-            }
-        }
-    }
-
-    let test_sets_vec = test_sets.sets.iter().map(|(k,v)| v.clone()).collect();
-    let mut lost_alignment_result = check_for_lost_alignment(&test_sets_vec);
-
-    LayoutViolations {
-        overflows: overflows,
-        overlaps: overlaps,
-        alignment_changes: lost_alignment_result,
-    }
+    RuleRegistry::with_default_rules().check(test_sets)
 }
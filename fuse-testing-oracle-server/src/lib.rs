@@ -0,0 +1,125 @@
+extern crate sdl2;
+extern crate serde;
+#[macro_use] extern crate serde_derive;
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate winapi;
+extern crate user32;
+extern crate kernel32;
+extern crate gdi32;
+extern crate rand;
+extern crate mio;
+#[macro_use] extern crate itertools;
+
+pub mod server;
+pub mod test_sets;
+pub mod test_runner;
+pub mod drawing;
+pub mod random_color;
+pub mod layout_validator;
+pub mod baseline_finder;
+pub mod suppressions;
+pub mod offline;
+pub mod codec;
+pub mod protocol;
+pub mod app_log;
+pub mod json_report;
+pub mod junit_report;
+pub mod html_report;
+pub mod sarif_report;
+pub mod svg;
+pub mod text_report;
+
+//paths the app reports are in the format of the machine it was built on, this gives them forward slashes everywhere
+pub fn normalize_path(path: &str) -> String {
+    path.replace("\\", "/")
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum MessageType {
+    LayoutData,
+    RequestLayoutData,
+    ResizeWindow,
+    Hello,
+    Welcome,
+    LayoutReady,
+    Heartbeat,
+    Log,
+    None
+}
+
+impl MessageType {
+    pub fn from_i32(val: i32) -> Option<MessageType> {
+        match val {
+            0 => Some(MessageType::LayoutData),
+            1 => Some(MessageType::RequestLayoutData),
+            2 => Some(MessageType::ResizeWindow),
+            3 => Some(MessageType::Hello),
+            4 => Some(MessageType::Welcome),
+            5 => Some(MessageType::LayoutReady),
+            6 => Some(MessageType::Heartbeat),
+            7 => Some(MessageType::Log),
+            _ => None,
+        }
+    }
+
+    pub fn as_i32(&self) -> i32 {
+        match self {
+            &MessageType::LayoutData => 0,
+            &MessageType::RequestLayoutData => 1,
+            &MessageType::ResizeWindow => 2,
+            &MessageType::Hello => 3,
+            &MessageType::Welcome => 4,
+            &MessageType::LayoutReady => 5,
+            &MessageType::Heartbeat => 6,
+            &MessageType::Log => 7,
+            &MessageType::None => -1,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MessageData {
+    pub json_string: String
+}
+
+impl MessageData {
+    pub fn as_json_string(&self) -> String {
+        let serialized = serde_json::to_string(self).unwrap();
+        serialized
+    }
+
+    pub fn from_json_string(json: String) -> MessageData {
+        MessageData {
+            json_string: json
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Message {
+    pub message_type: MessageType,
+    pub length: i32,
+    pub data: MessageData,
+}
+
+impl Message {
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut ret = Vec::new();
+        let message_type = format!("{}", self.message_type.as_i32());
+        let data_json_string = self.data.as_json_string();
+        let message_data = data_json_string.as_bytes();
+        let message_length = format!("{}", message_data.len());
+
+        ret.extend_from_slice(message_type.as_bytes());
+        ret.push(b'\n');
+        ret.extend_from_slice(message_length.as_bytes());
+        ret.push(b'\n');
+        ret.extend_from_slice(data_json_string.as_bytes());
+        ret
+    }
+
+    pub fn encode(&mut self) -> Vec<u8> {
+        self.as_bytes()
+    }
+}
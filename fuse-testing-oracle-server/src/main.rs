@@ -1,16 +1,9 @@
+extern crate fuse_testing_oracle_server;
 extern crate sdl2;
-#[macro_use] extern crate serde_derive;
-extern crate serde_json;
-extern crate serde_yaml;
-extern crate winapi;
-extern crate user32;
-extern crate kernel32;
-extern crate gdi32;
 extern crate clap;
 extern crate rand;
-extern crate mio;
 extern crate time;
-#[macro_use] extern crate itertools;
+extern crate itertools;
 
 use sdl2::pixels::Color;
 use sdl2::rect::{Point,Rect};
@@ -22,30 +15,36 @@ use sdl2::ttf::{init as init_ttf};
 
 use itertools::*;
 
-pub mod server;
-pub mod test_sets;
-pub mod test_runner;
-pub mod drawing;
-pub mod random_color;
-pub mod layout_validator;
-pub mod baseline_finder;
-
-use baseline_finder::*;
-use test_sets::*;
-
-use layout_validator::*;
-use layout_validator::validity_rules::*;
-use layout_validator::overlap_and_overflow::{OverflowRect};
-use test_runner::*;
-use test_runner::test_script::*;
-use drawing::*;
+use fuse_testing_oracle_server::baseline_finder::*;
+use fuse_testing_oracle_server::suppressions::*;
+use fuse_testing_oracle_server::json_report::generate_json_report;
+use fuse_testing_oracle_server::text_report::{generate_violations_report,generate_failures_report};
+use fuse_testing_oracle_server::html_report::generate_html_report;
+use fuse_testing_oracle_server::junit_report::{generate_junit_test_suite,generate_junit_report};
+use fuse_testing_oracle_server::sarif_report::generate_sarif_report;
+use fuse_testing_oracle_server::svg::{save_svg_test_sets,save_svg_violations};
+use fuse_testing_oracle_server::test_sets::*;
+use fuse_testing_oracle_server::offline::*;
+
+use fuse_testing_oracle_server::layout_validator::*;
+use fuse_testing_oracle_server::layout_validator::validity_rules::*;
+use fuse_testing_oracle_server::layout_validator::rules::{RuleRegistry,RulesConfig,Severity};
+use fuse_testing_oracle_server::layout_validator::overlap_and_overflow::{OverflowRect};
+use fuse_testing_oracle_server::test_runner::*;
+use fuse_testing_oracle_server::test_runner::test_script::*;
+use fuse_testing_oracle_server::drawing::*;
 
 use std::net::*;
 use std::io::Result;
 
-use server::{Server};
+use fuse_testing_oracle_server::server::{Server,ServerError,LayoutMismatch};
+use fuse_testing_oracle_server::layout_validator::Nodes;
+use fuse_testing_oracle_server::app_log::LogLine;
+use fuse_testing_oracle_server::layout_validator::stability::{UnstableNodes, find_unstable_nodes};
 
 use std::thread;
+use std::panic::{self,AssertUnwindSafe};
+use std::any::Any;
 
 use std::convert::From;
 
@@ -54,6 +53,7 @@ use std::env;
 use std::collections::{HashSet,HashMap};
 use std::hash::{Hash,Hasher};
 use std::cmp::{max,min};
+use std::time::Duration;
 
 use std::path::{Path,PathBuf};
 use std::io::Write;
@@ -61,8 +61,9 @@ use std::fmt::Write as WriteFmt;
 
 
 use std::sync::mpsc::*;
-use std::sync::RwLock;
+use std::sync::{RwLock,Mutex};
 use std::sync::Arc;
+use std::collections::VecDeque;
 
 use std::rc::Rc;
 
@@ -74,149 +75,44 @@ use rand::{Rng, thread_rng};
 
 use time::{now,Tm};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub enum MessageType {
-    LayoutData,
-    RequestLayoutData,
-    None
-}
-
-impl MessageType {
-    pub fn new(val: i32) -> MessageType {
-        if val == 0 {
-            MessageType::LayoutData
-        } else {
-            MessageType::None
-        }
-    }
-
-    pub fn as_i32(&self) -> i32 {
-        match self {
-            &MessageType::LayoutData => 0,
-            &MessageType::RequestLayoutData => 1,
-            &MessageType::None => -1,
-        }
-    }
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct MessageData {
-    pub json_string: String
-}
-
-impl MessageData {
-    pub fn as_json_string(&self) -> String {
-        let serialized = serde_json::to_string(self).unwrap();
-        serialized
-    }
-
-    pub fn from_json_string(json: String) -> MessageData {
-        MessageData {
-            json_string: json
-        }
-    }
-}
-
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Message {
-    pub message_type: MessageType,
-    pub length: i32,
-    pub data: MessageData,
-}
-
-impl Message {
-    pub fn as_bytes(&self) -> Vec<u8> {
-        let mut ret = Vec::new();
-        let message_type = format!("{}", self.message_type.as_i32());
-        let data_json_string = self.data.as_json_string();
-        let message_data = data_json_string.as_bytes();
-        let message_length = format!("{}", message_data.len());
-
-        ret.extend_from_slice(message_type.as_bytes());
-        ret.push(b'\n');
-        ret.extend_from_slice(message_length.as_bytes());
-        ret.push(b'\n');
-        ret.extend_from_slice(data_json_string.as_bytes());
-        ret
-    }
-
-    pub fn decode(buf: &[u8]) -> Option<(Message,u32)> {
-        let buffer_string = String::from_utf8(Into::<Vec<u8>>::into(buf)).unwrap();
-        //println!("BufferString: {}", buffer_string);
-        let mut buffer = buf.to_vec();
-        let mut bytes_read: u32 = 0;
-        if let Some(i) = buffer.iter().position(|&b| b == b'\n') {
-            let message_type_string = String::from_utf8(buffer.drain(0..i).collect()).unwrap();
-            let message_type = MessageType::new(message_type_string.parse::<i32>().unwrap());
-
-            //removes the \n
-            buffer.remove(0);
-            bytes_read += (i+1) as u32;
-
-            if let Some(i2) = buffer.iter().position(|&b| b == b'\n') {
-                let message_length_string = String::from_utf8(buffer.drain(0..i2).collect()).unwrap();
-                let message_length : i32 = message_length_string.parse().unwrap();
-                //removes the \n
-                buffer.remove(0);
-                bytes_read += (i2+1) as u32;
-
-                if buffer.len() >= message_length as usize {
-                    let data = buffer.drain(0..(message_length as usize));
-                    let data_string = String::from_utf8(data.collect()).unwrap();
-                    let ret_msg = Message {
-                        message_type: message_type,
-                        length: message_length,
-                        data: MessageData::from_json_string(data_string),
-                    };
-                    bytes_read += message_length as u32;
-                    Some((ret_msg,bytes_read as u32))
-                } else {
-                    None
-                }
-            } else {
-                None
-            }
-        } else {
-            None
-        }
-
-    }
-
-    pub fn encode(&mut self) -> Vec<u8> {
-        self.as_bytes()
-    }
-}
-
 fn enter_to_continue() {
     println!("Press enter to continue");
     let mut input = String::new();
     std::io::stdin().read_line(&mut input);
 }
 
-fn run_all_tests(draw_context: &mut DrawContext,
+fn run_all_tests(draw_context: Option<&mut DrawContext>,
+                 rules: &RuleRegistry,
+                 suppressions: &Vec<Suppression>,
                  test_sets: &TestSets,
                  name: &str,
                  baseline_threshold: Option<f32>,
                  overlap_overflow_threshold: Option<f32>) -> LayoutViolations {
     println!("Running tests");
 
-    for (_,ref test_set) in &test_sets.sets {
-        let ref id = test_set.id;
-        let nodes = test_set.nodes.clone();
-        let ref tab_stops = test_set.tab_stops;
-        let ref screen_size = test_set.screen_size;
-        draw_context.renderer.window_mut().unwrap().set_size(screen_size.0 as u32, screen_size.1 as u32);
-        println!("Rendering window_size: {:?}", screen_size);
+    if let Some(draw_context) = draw_context {
+        for (_,ref test_set) in &test_sets.sets {
+            let ref id = test_set.id;
+            let nodes = test_set.nodes.clone();
+            let ref tab_stops = test_set.tab_stops;
+            let ref screen_size = test_set.screen_size;
+            draw_context.set_size(screen_size.0 as u32, screen_size.1 as u32);
+            println!("Rendering window_size: {:?}", screen_size);
 
-        draw_context.draw_nodes(&nodes);
-        let path = format!("output/{}/size{}x{}.png", name, screen_size.0, screen_size.1);
-        draw_context.save_to_png(&path);
+            draw_context.draw_nodes(&nodes);
+            let path = format!("output/{}/size{}x{}.png", name, screen_size.0, screen_size.1);
+            draw_context.save_to_png(&path);
 
-        draw_context.present();
+            draw_context.present();
+        }
     }
 
     //LAYOUT VALIDATION
-    let mut violations = validate_layout(&test_sets);
+    let mut violations = validate_layout_with_rules(rules, &test_sets);
+
+    //SUPPRESSIONS
+
+    remove_suppressed_violations(&mut violations, &test_sets, suppressions);
 
     //FINDING BASELINE
 
@@ -249,75 +145,392 @@ impl Hash for AlignmentChangeKey {
     }
 }
 
-pub fn generate_violations_report(test_sets: &TestSets, violations: &LayoutViolations) -> String {
-    let mut ret = String::new();
+//make sure the output directories exists, returns the name of the run directory inside output/
+fn create_output_directory(name: &str) -> String {
+    let now = now();
 
+    let mut p = PathBuf::new();
+    p.push("output");
+    let directory_name = format!("{}_{}", name, now.ctime());
+    let directory_name = directory_name.replace(" ", "_");
+    let directory_name = directory_name.replace(":", "_");
+    println!("DirName: {}", directory_name);
+    p.push(&directory_name);
+    std::fs::create_dir_all(p);
 
-    let mut sorted_by_test_set: Vec<LayoutViolation> = violations.all();
+    directory_name
+}
 
-    let violation_type_counts = sorted_by_test_set.iter().fold((0,0,0), |acc, v| {
-        match v {
-            &LayoutViolation::Overlap { .. } => (acc.0 + 1,acc.1,acc.2),
-            &LayoutViolation::Overflow { .. } => (acc.0, acc.1 + 1, acc.2),
-            &LayoutViolation::AlignmentLost { .. } => (acc.0, acc.1, acc.2 + 1),
-        }
-    });
+//writes the report, and report.json for other tools. image_extension is the format of the violation images, if they are saved too
+fn write_report(name: &str,
+                test_sets: Option<&TestSets>,
+                violations: Option<&LayoutViolations>,
+                failures: &Vec<(TestSetId, String, ServerError)>,
+                mismatches: &Vec<LayoutMismatch>,
+                crashes: &Vec<AppCrash>,
+                directory_name: &str,
+                image_extension: Option<&str>) {
+    let mut report = generate_failures_report(failures, mismatches, crashes);
+    if let (Some(test_sets), Some(violations)) = (test_sets, violations) {
+        report.push_str(&generate_violations_report(test_sets, violations));
+    }
 
-    let total_errors = violation_type_counts.0 + violation_type_counts.1 + violation_type_counts.2;
-    let total_overlaps = violation_type_counts.0;
-    let total_overflows = violation_type_counts.1;
-    let total_layout_changes = violation_type_counts.2;
+    let report_file_name = format!("./output/{}/report", directory_name);
+    println!("Report_file_name: {}", report_file_name);
+    write_file(&report_file_name, &report);
 
-    let mut lines = Vec::new();
+    let json_report = generate_json_report(name, test_sets, violations, failures, mismatches, crashes, image_extension);
+    write_file(&format!("./output/{}/report.json", directory_name), &json_report);
+
+    let html_report = generate_html_report(name, test_sets, violations, failures, mismatches, crashes, image_extension);
+    write_file(&format!("./output/{}/report.html", directory_name), &html_report);
+}
+
+//a report that can't be written is reported, the rest of the run goes on
+fn write_file(path: &str, contents: &str) {
+    if let Err(e) = std::fs::File::create(path).and_then(|mut f| f.write_all(contents.as_bytes())) {
+        println!("Could not write {}: {}", path, e);
+    }
+}
 
+//what the app printed and logged goes next to the report
+fn write_app_log(log: &Vec<LogLine>, directory_name: &str) {
+    let mut contents = String::new();
+    for line in log {
+        writeln!(contents, "{}", line);
+    }
+    write_file(&format!("./output/{}/log", directory_name), &contents);
+}
 
+//the default rules with the test script's settings applied
+fn rule_registry(rules_config: &RulesConfig) -> RuleRegistry {
+    let mut rules = RuleRegistry::with_default_rules();
+    rules.configure(rules_config);
+    rules
+}
 
-    let mut test_set = -1;
-    let mut violation_id = 0;
-    for violation in &sorted_by_test_set {
-        match violation {
-            &LayoutViolation::Overlap { node1, node2, intersection_rect, test_set} => {
-                let ts = test_sets.sets.get(&test_set).unwrap();
+//how many points apart node sides can be and still be on the same tab stop
+fn tab_stop_merge_threshold(rules_config: &RulesConfig) -> f32 {
+    rules_config.get("AlignmentLost").and_then(|c| c.tolerance).unwrap_or(DEFAULT_TAB_STOP_MERGE_THRESHOLD)
+}
 
-                let n1 = ts.nodes.get_from_id(node1).unwrap();
-                let n2 = ts.nodes.get_from_id(node2).unwrap();
-                lines.push(format!("- Overlap - test-set:{} => {} - {}", test_set, n1, n2));
-            },
-            &LayoutViolation::Overflow { node1, node2, ref overflow_rect, test_set } => {
-                let ts = test_sets.sets.get(&test_set).unwrap();
+/*The baseline thresholds for alignment changes and for overlaps and overflows. The -b and -o
+flags win over the test script. Overlaps and overflows share a threshold, Overlap's is used if
+both rules have one. It is only used along with an alignment change threshold.*/
+fn baseline_thresholds(rules_config: &RulesConfig,
+                       baseline_threshold: Option<f32>,
+                       overlap_overflow_threshold: Option<f32>) -> (Option<f32>, Option<f32>) {
+    let from_script = |name: &str| rules_config.get(name).and_then(|c| c.baseline_threshold);
+    (baseline_threshold.or(from_script("AlignmentLost")),
+     overlap_overflow_threshold.or(from_script("Overlap")).or(from_script("Overflow")))
+}
 
-                let n1 = ts.nodes.get_from_id(node1).unwrap();
-                let n2 = ts.nodes.get_from_id(node2).unwrap();
-                lines.push(format!("- Overflow - test-set:{} => {} - {}", test_set.0, n1, n2));
-            },
-            &LayoutViolation::AlignmentLost { a:a, b:b, count:count, test_sets: ref tss } => {
-                let ts = test_sets.sets.get(&tss.get(0).unwrap()).unwrap();
+/*What the process exits with when --fail-on is given: 1 when violations at least that severe
+are left after suppressions and the baseline, 2 when a project could not be tested at all.*/
+fn exit_code(fail_on: Option<Severity>, failing_violations: usize, failed_projects: usize) -> i32 {
+    match fail_on {
+        Some(fail_on) => {
+            if failed_projects > 0 {
+                println!("Failing, {} projects could not be tested", failed_projects);
+                2
+            } else if failing_violations > 0 {
+                println!("Failing, {} violations are {} or worse", failing_violations, fail_on);
+                1
+            } else {
+                0
+            }
+        },
+        None => 0,
+    }
+}
 
-                let n1_line = ts.nodes.get_from_id(a.node).unwrap().node_data.line;
-                let n2_line = ts.nodes.get_from_id(b.node).unwrap().node_data.line;
+fn write_junit_report(test_suites: &Vec<String>, path: &str) {
+    println!("Writing JUnit report to {}", path);
+    write_file(path, &generate_junit_report(test_suites));
+}
 
-                let mut l = String::new();
+fn write_sarif_report(projects: &Vec<(&str, &TestSets, &LayoutViolations)>, source_root: Option<&str>, path: &str) {
+    println!("Writing SARIF report to {}", path);
+    write_file(path, &generate_sarif_report(projects, source_root));
+}
 
-                write!(&mut l, "- AlignmentLost => {} @ L:{} - {} @ L:{} - aligned in {} test sets", a, n1_line, b, n2_line, count);
-                lines.push(l);
-            },
+//returns None when there was nothing to validate, or the layouts could not be read
+fn run_offline(layouts_dir: &Path,
+               rules_config: &RulesConfig,
+               suppressions: &Vec<Suppression>,
+               baseline_threshold: Option<f32>,
+               overlap_overflow_threshold: Option<f32>,
+               junit_path: Option<&str>,
+               sarif_path: Option<&str>,
+               sarif_root: Option<&str>,
+               save_png: bool,
+               save_svg: bool,
+               svg_tab_stops: bool) -> Option<LayoutViolations> {
+    let name = layouts_dir.file_name().and_then(|n| n.to_str()).unwrap_or("offline").to_string();
+    let test_data = match load_test_data_from_dir(layouts_dir) {
+        Ok(test_data) => test_data,
+        Err(e) => {
+            println!("Could not read the layouts directory {:?}: {}", layouts_dir, e);
+            return None;
         }
-        violation_id += 1;
+    };
+    if test_data.len() == 0 {
+        println!("Found no layouts in {:?}, nothing to validate", layouts_dir);
+        return None;
     }
 
-    writeln!(ret, "* # of test sets: - {}", test_sets.sets.iter().len());
-    writeln!(ret, "* Total errors: - {}", total_errors);
-    writeln!(ret, "\t* Overlaps ------------- : {}", total_overlaps);
-    writeln!(ret, "\t* Overflows ------------ : {}", total_overflows);
-    writeln!(ret, "\t* Total alignment changes: {}", total_layout_changes);
+    let directory_name = create_output_directory(&name);
+
+    let test_sets = generate_test_sets(test_data, HashMap::new(), tab_stop_merge_threshold(rules_config));
+    let rules = rule_registry(rules_config);
+    let (baseline_threshold, overlap_overflow_threshold) = baseline_thresholds(rules_config, baseline_threshold, overlap_overflow_threshold);
+
+    //the pngs are always drawn headless here, there is no app to look at
+    let ttf_context = init_ttf().unwrap();
+    let mut draw_context = if save_png {
+        let window_size = (600, 1200);
+        let font = ttf_context.load_font("./assets/Roboto-Regular.ttf", 14).unwrap();
+        Some(DrawContext::new(window_size, font, init_headless_renderer(window_size)))
+    } else {
+        None
+    };
+    let violations = run_all_tests(draw_context.as_mut(), &rules, suppressions, &test_sets, &directory_name, baseline_threshold, overlap_overflow_threshold);
+
+    //the recorded layouts have no screen size names, so they are named by size
+    let mut screen_sizes: Vec<(TestSetId, String)> = test_sets.sets.values()
+        .map(|ts| (ts.id, format!("{}x{}", ts.screen_size.0, ts.screen_size.1)))
+        .collect();
+    screen_sizes.sort_by_key(|&(id, _)| id.0);
+
+    //the reports link the pngs when both formats are saved
+    let image_extension = if save_png { "png" } else { "svg" };
+    write_report(&name, Some(&test_sets), Some(&violations), &Vec::new(), &Vec::new(), &Vec::new(), &directory_name, Some(image_extension));
+
+    if let Some(ref mut draw_context) = draw_context {
+        draw_context.save_overflow_violations(&test_sets, &violations, &directory_name);
+        draw_context.save_overlap_violations(&test_sets, &violations, &directory_name);
+        draw_context.save_alignment_changed_violations(&test_sets, &violations, &screen_sizes, &directory_name);
+        draw_context.save_custom_violations(&test_sets, &violations, &directory_name);
+    }
+    if save_svg {
+        save_svg_test_sets(&test_sets, &directory_name, svg_tab_stops);
+        save_svg_violations(&test_sets, &violations, &screen_sizes, &directory_name, svg_tab_stops);
+    }
 
-    for l in lines {
-        writeln!(&mut ret, "{}", l);
+    if let Some(junit_path) = junit_path {
+        let test_suite = generate_junit_test_suite(&name, &screen_sizes, Some(&test_sets), Some(&violations), &Vec::new(), &Vec::new(), Some((&directory_name, image_extension)));
+        write_junit_report(&vec![test_suite], junit_path);
     }
+    if let Some(sarif_path) = sarif_path {
+        write_sarif_report(&vec![(name.as_str(), &test_sets, &violations)], sarif_root, sarif_path);
+    }
+    Some(violations)
+}
 
-    println!("{}",ret);
+//time given to the app between two captures of the same screen size
+const CAPTURE_INTERVAL_MS: u64 = 200;
+
+//everything the apps of one project gave us, captured on a worker thread and validated on the main thread
+struct ProjectCapture {
+    name: String,
+    directory_name: String,
+    test_data: Vec<(TestSetId, Nodes, (i32,i32))>,
+    failures: Vec<(TestSetId, String, ServerError)>,
+    mismatches: Vec<LayoutMismatch>,
+    crashes: Vec<AppCrash>,
+    //what the apps printed and logged, one instance after the other
+    log: Vec<LogLine>,
+    //nodes that moved between the captures of a screen size, only found when capturing more than once
+    unstable_nodes: HashMap<TestSetId, UnstableNodes>,
+    //the test script's rule settings with the project's own on top
+    rules: RulesConfig,
+    suppressions: Vec<Suppression>,
+}
 
-    ret
+//what a panic was started with, so it can be reported as a failure
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
+
+/*What is left of a project the test runner failed on before its apps were started, like one
+that did not build. All its screen sizes are failed with the error.*/
+fn failed_capture(project: &Project, test_script: &TestScript, error: ServerError) -> ProjectCapture {
+    let name = Path::new(&project.path).file_stem().and_then(|n| n.to_str()).unwrap_or("project").to_string();
+    println!("Could not test {}: {}", name, error);
+    ProjectCapture {
+        name: name.clone(),
+        directory_name: create_output_directory(&name),
+        test_data: Vec::new(),
+        failures: test_script.screen_sizes.iter().enumerate().map(|(i, s)| (TestSetId(i as i32), s.name.clone(), error.clone())).collect(),
+        mismatches: Vec::new(),
+        crashes: Vec::new(),
+        log: Vec::new(),
+        unstable_nodes: HashMap::new(),
+        rules: test_script.rules_for(project),
+        suppressions: test_script.suppressions_for(project),
+    }
+}
+
+//builds the project if needed, returns the executable with its arguments and the project name
+fn prepare_project(project: &Project) -> (PathBuf, Vec<String>, String) {
+    if let Some(ref executable) = project.executable {
+        let name = Path::new(&project.path).file_stem().unwrap().to_str().unwrap().to_string();
+        (PathBuf::from(executable), project.arguments.clone(), name)
+    } else {
+        let (exe_path, project_name) = TestRunnerContext::build_and_get_exe_path(Path::new(&project.path));
+        (exe_path, Vec::new(), project_name)
+    }
+}
+
+//runs one copy of the app and captures the given screen sizes (indices into the test script) with it
+fn capture_instance(server: &Server,
+                    exe_path: &Path,
+                    arguments: &Vec<String>,
+                    test_script: &TestScript,
+                    screen_size_ids: &Vec<usize>,
+                    directory_name: &str,
+                    record: bool,
+                    captures_per_size: usize,
+                    capture: &mut ProjectCapture) {
+    let screen_sizes: Vec<(i32, &ScreenSize)> = screen_size_ids.iter()
+        .map(|&i| (i as i32, &test_script.screen_sizes[i]))
+        .collect();
+
+    let mut session = server.new_session();
+    let mut test_runner_context = TestRunnerContext::start_executable(exe_path, arguments, &session.key());
+
+    if let Err(e) = test_runner_context.connect(&mut session) {
+        println!("Giving up on an instance of {}: {}", capture.name, e);
+        for &(id, screen_size) in &screen_sizes {
+            capture.failures.push((TestSetId(id), screen_size.name.clone(), e.clone()));
+        }
+        test_runner_context.kill_process();
+        capture.log.extend(test_runner_context.log().lines());
+        return;
+    }
+
+    let mut test_data = Vec::new();
+    let mut unstable_nodes = HashMap::new();
+    let results = test_runner_context.test_screen_sizes(server, &mut session, &screen_sizes, |session, id, screen_size| {
+
+
+        //enter_to_continue();
+        /*When asked to capture more than once, the layouts are compared and whatever moved in
+        between is reported as unstable instead of being validated. The last capture is kept.*/
+        let mut captures = Vec::new();
+        let mut last = None;
+        for i in 0..captures_per_size {
+            if i > 0 {
+                thread::sleep(Duration::from_millis(CAPTURE_INTERVAL_MS));
+            }
+            let (nodes, id, json_string) = session.request_layout_data(id)?;
+            captures.push(nodes.clone());
+            last = Some((nodes, id, json_string));
+        }
+        let (nodes, id, json_string) = last.unwrap();
+
+        let size = (screen_size.width, screen_size.height);
+        if record {
+            record_layout(directory_name, TestSetId(id), &screen_size.name, size, &json_string);
+        }
+        let unstable = find_unstable_nodes(&captures);
+        if unstable.len() > 0 {
+            println!("{} nodes moved between captures at {}", unstable.len(), screen_size.name);
+        }
+        unstable_nodes.insert(TestSetId(id), unstable);
+        test_data.push((TestSetId(id),nodes,size));
+        Ok(())
+    });
+
+    capture.test_data.extend(test_data);
+    capture.unstable_nodes.extend(unstable_nodes);
+    capture.failures.extend(results.failures);
+    capture.crashes.extend(results.crashes);
+    capture.mismatches.extend(results.mismatches);
+
+    test_runner_context.kill_process();
+    capture.log.extend(test_runner_context.log().lines());
+    session.close();
+}
+
+/*Runs the project's instances side by side, each on its own share of the screen sizes,
+and merges what they captured. The test set ids are the screen size indices, so they don't
+depend on which instance captured what.*/
+fn capture_project(server: &Server, project: &Project, test_script: Arc<TestScript>, record: bool, captures_per_size: usize) -> ProjectCapture {
+    let (exe_path, arguments, name) = prepare_project(project);
+    let directory_name = create_output_directory(&name);
+
+    let instances = max(1, min(project.instances, test_script.screen_sizes.len()));
+    let mut handles = Vec::new();
+    for instance in 0..instances {
+        let screen_size_ids: Vec<usize> = (0..test_script.screen_sizes.len())
+            .filter(|i| i % instances == instance)
+            .collect();
+        let server = server.clone();
+        let exe_path = exe_path.clone();
+        let arguments = arguments.clone();
+        let test_script = test_script.clone();
+        let name = name.clone();
+        let directory_name = directory_name.clone();
+        let instance_screen_size_ids = screen_size_ids.clone();
+        handles.push((instance_screen_size_ids, thread::spawn(move || {
+            let mut capture = ProjectCapture {
+                name: name,
+                directory_name: directory_name.clone(),
+                test_data: Vec::new(),
+                failures: Vec::new(),
+                mismatches: Vec::new(),
+                crashes: Vec::new(),
+                log: Vec::new(),
+                unstable_nodes: HashMap::new(),
+                rules: RulesConfig::new(),
+                suppressions: Vec::new(),
+            };
+            capture_instance(&server, &exe_path, &arguments, &test_script, &screen_size_ids, &directory_name, record, captures_per_size, &mut capture);
+            capture
+        })));
+    }
+
+    let mut capture = ProjectCapture {
+        name: name,
+        directory_name: directory_name,
+        test_data: Vec::new(),
+        failures: Vec::new(),
+        mismatches: Vec::new(),
+        crashes: Vec::new(),
+        log: Vec::new(),
+        unstable_nodes: HashMap::new(),
+        rules: test_script.rules_for(project),
+        suppressions: test_script.suppressions_for(project),
+    };
+    for (screen_size_ids, handle) in handles {
+        let instance_capture = match handle.join() {
+            Ok(instance_capture) => instance_capture,
+            Err(payload) => {
+                //what the instance captured is gone with its thread, so all its screen sizes are failed
+                let error = ServerError::RunnerFailed(panic_message(payload));
+                println!("An instance of {} failed: {}", capture.name, error);
+                for i in screen_size_ids {
+                    capture.failures.push((TestSetId(i as i32), test_script.screen_sizes[i].name.clone(), error.clone()));
+                }
+                continue;
+            },
+        };
+        capture.test_data.extend(instance_capture.test_data);
+        capture.failures.extend(instance_capture.failures);
+        capture.mismatches.extend(instance_capture.mismatches);
+        capture.crashes.extend(instance_capture.crashes);
+        capture.log.extend(instance_capture.log);
+        capture.unstable_nodes.extend(instance_capture.unstable_nodes);
+    }
+    capture.test_data.sort_by_key(|&(id, _, _)| id.0);
+    capture
 }
 
 fn main() {
@@ -346,12 +559,81 @@ fn main() {
              .short("o")
              .takes_value(true)
              .help("Threshold for including overlaps and overflows in baseline."))
+        .arg(Arg::with_name("layouts")
+             .short("l")
+             .long("layouts")
+             .takes_value(true)
+             .help("Validates a directory of recorded LayoutChanged json files (one per screen size) instead of running an app. Needs no display. The Rules and Suppressions of the test script given with -t are used, if any."))
+        .arg(Arg::with_name("connect_timeout")
+             .long("connect-timeout")
+             .takes_value(true)
+             .help("Seconds to wait for the app to connect before the project is marked as failed"))
+        .arg(Arg::with_name("response_timeout")
+             .long("response-timeout")
+             .takes_value(true)
+             .help("Seconds to wait for the app to answer a resize or layout request before the screen size is marked as failed"))
+        .arg(Arg::with_name("layout_ready_timeout")
+             .long("layout-ready-timeout")
+             .takes_value(true)
+             .help("Seconds to wait for the app's layout to settle after a resize before capturing it anyway"))
+        .arg(Arg::with_name("jobs")
+             .short("j")
+             .long("jobs")
+             .takes_value(true)
+             .help("How many projects to test at the same time, defaults to 1"))
+        .arg(Arg::with_name("captures")
+             .long("captures")
+             .takes_value(true)
+             .help("How many times to capture each screen size. With 2 or more, nodes that move between captures are reported as unstable instead of being validated"))
+        .arg(Arg::with_name("record")
+             .long("record")
+             .help("Records every layout received during auto run into the run's output directory, so it can be replayed with -l"))
+        .arg(Arg::with_name("headless")
+             .long("headless")
+             .help("Draws the images with a software renderer instead of a window, for machines without a display or GPU"))
+        .arg(Arg::with_name("images")
+             .long("images")
+             .takes_value(true)
+             .possible_values(&["png", "svg", "both"])
+             .help("The format of the screen size and violation images, defaults to png. With recorded layouts (-l) the PNGs are always drawn headless"))
+        .arg(Arg::with_name("svg_tab_stops")
+             .long("svg-tab-stops")
+             .help("Draws the tab stops of each screen size in the SVG images"))
+        .arg(Arg::with_name("fail_on")
+             .long("fail-on")
+             .takes_value(true)
+             .possible_values(&["error", "warning", "info"])
+             .help("Exits with 1 when violations of this severity or worse are left after suppressions and the baseline, and with 2 when a project could not be tested"))
+        .arg(Arg::with_name("junit")
+             .long("junit")
+             .takes_value(true)
+             .help("Also writes a JUnit XML report to this file, with a test suite for each project and a test case for each screen size"))
+        .arg(Arg::with_name("sarif")
+             .long("sarif")
+             .takes_value(true)
+             .help("Also writes a SARIF 2.1 report to this file, with a result for each violation located at the ux lines of its elements"))
+        .arg(Arg::with_name("sarif_root")
+             .long("sarif-root")
+             .takes_value(true)
+             .help("The directory the ux file paths in the SARIF report are made relative to, usually the root of the repository"))
         .get_matches();
 
 
     let project_path = matches.value_of("project");
-    let test_script_path = matches.value_of("test_script").unwrap();
     let auto_run = matches.is_present("auto_run");
+    let layouts_dir = matches.value_of("layouts");
+    let record = matches.is_present("record");
+    let captures_per_size = matches.value_of("captures").map_or(1, |c| max(1, c.parse::<usize>().unwrap()));
+    let fail_on = matches.value_of("fail_on").map(|s| s.parse::<Severity>().unwrap());
+    let junit_path = matches.value_of("junit");
+    let sarif_path = matches.value_of("sarif");
+    let sarif_root = matches.value_of("sarif_root");
+    let images = matches.value_of("images").unwrap_or("png");
+    let save_png = images != "svg";
+    let save_svg = images != "png";
+    //the reports link the pngs when both formats are saved
+    let image_extension = if save_png { "png" } else { "svg" };
+    let svg_tab_stops = matches.is_present("svg_tab_stops");
 
     let baseline_match = matches.value_of("baseline");
     let oo_baseline_match = matches.value_of("overlap_overflow_baseline_threshold");
@@ -366,14 +648,38 @@ fn main() {
         overlap_overflow_threshold = Some(oo_baseline_match.parse::<f32>().unwrap());
     }
 
+    if let Some(layouts_dir) = layouts_dir {
+        let (rules_config, suppressions) = match matches.value_of("test_script") {
+            Some(path) => {
+                let test_script = TestScript::from_path(path);
+                (test_script.rules, test_script.suppressions)
+            },
+            None => (RulesConfig::new(), Vec::new()),
+        };
+        let code = match run_offline(Path::new(layouts_dir), &rules_config, &suppressions, baseline_threshold, overlap_overflow_threshold, junit_path, sarif_path, sarif_root, save_png, save_svg, svg_tab_stops) {
+            Some(violations) => exit_code(fail_on, fail_on.map_or(0, |s| violations.count_at_least(s)), 0),
+            None => exit_code(fail_on, 0, 1),
+        };
+        std::process::exit(code);
+    }
 
-    let (sdl_context, mut window) = init_sdl();
-    let window_size = window.size();
-    let mut renderer = window.renderer().build().unwrap();
+    let test_script_path = matches.value_of("test_script").unwrap();
+
+    //headless runs draw into an image in memory, without opening a window
+    let headless = matches.is_present("headless");
+    let mut sdl_context = None;
+    let (window_size, mut renderer) = if headless {
+        let window_size = (600, 1200);
+        (window_size, init_headless_renderer(window_size))
+    } else {
+        let (context, mut window) = init_sdl();
+        sdl_context = Some(context);
+        (window.size(), window.renderer().build().unwrap())
+    };
 
 
     renderer.set_blend_mode(BlendMode::Blend);
-    let mut event_pump = sdl_context.event_pump().unwrap();
+    let mut event_pump = sdl_context.as_ref().map(|c| c.event_pump().unwrap());
 
     println!("WindowSize: {:?}", window_size);
 
@@ -400,65 +706,132 @@ fn main() {
     println!("We have test script path: {}", test_script_path);
     println!("Starting preview for project: {:?}", project_path);
 
-    let test_script = TestScript::from_path(test_script_path);
+    let test_script = Arc::new(TestScript::from_path(test_script_path));
 
     println!("Starting server, listening for app to connect");
     let mut server = Server::start_new("127.0.0.1:12345");
 
-    if auto_run {
-
-        for project in &test_script.projects {
-
-            let (exe_path, name) = TestRunnerContext::build_and_get_exe_path(Path::new(&project.path));
-            let mut test_runner_context = TestRunnerContext::start_preview_for_example(exe_path.as_path());
-
-            server.wait_for_client();
-            println!("Client connected");
-            thread::sleep_ms(500);
-
-            let mut test_data = Vec::new();
-            test_runner_context.test_all_screen_sizes(&test_script, |id, screen_size| {
-
+    if let Some(connect_timeout) = matches.value_of("connect_timeout") {
+        server.set_connect_timeout(Duration::from_secs(connect_timeout.parse::<u64>().unwrap()));
+    }
+    if let Some(response_timeout) = matches.value_of("response_timeout") {
+        server.set_response_timeout(Duration::from_secs(response_timeout.parse::<u64>().unwrap()));
+    }
+    if let Some(layout_ready_timeout) = matches.value_of("layout_ready_timeout") {
+        server.set_layout_ready_timeout(Duration::from_secs(layout_ready_timeout.parse::<u64>().unwrap()));
+    }
 
-                //enter_to_continue();
-                thread::sleep_ms(400);
-                let (nodes, id) = server.request_layout_data(id).unwrap();
-                test_data.push((TestSetId(id),nodes,screen_size));
+    let mut failed_projects = Vec::new();
+    //violations at least as severe as --fail-on, over all projects
+    let mut failing_violations = 0;
+    let mut junit_test_suites = Vec::new();
+    //kept until the end of the run when a SARIF report is written, as it has all the projects in one run
+    let mut sarif_projects: Vec<(String, TestSets, LayoutViolations)> = Vec::new();
+    //the test set ids of an auto run are the indices of the screen sizes
+    let screen_size_names: Vec<(TestSetId, String)> = test_script.screen_sizes.iter().enumerate()
+        .map(|(i, screen_size)| (TestSetId(i as i32), screen_size.name.clone()))
+        .collect();
 
+    if auto_run {
+        let jobs = matches.value_of("jobs").map_or(1, |j| j.parse::<usize>().unwrap());
+
+        /*The apps are captured on worker threads. Validation and drawing stay on this thread
+        (the draw context can't be shared), and happen as each project's capture comes in.*/
+        let project_queue: Arc<Mutex<VecDeque<usize>>> = Arc::new(Mutex::new((0..test_script.projects.len()).collect()));
+        let (capture_tx, capture_rx) = channel::<ProjectCapture>();
+        for _ in 0..max(1, min(jobs, test_script.projects.len())) {
+            let server = server.clone();
+            let test_script = test_script.clone();
+            let project_queue = project_queue.clone();
+            let capture_tx = capture_tx.clone();
+            thread::spawn(move || {
+                loop {
+                    let next = project_queue.lock().unwrap().pop_front();
+                    let project_index = match next {
+                        Some(project_index) => project_index,
+                        None => break,
+                    };
+                    //a project that panics is reported as failed, and the next ones are still tested
+                    let project = &test_script.projects[project_index];
+                    let capture = match panic::catch_unwind(AssertUnwindSafe(|| capture_project(&server, project, test_script.clone(), record, captures_per_size))) {
+                        Ok(capture) => capture,
+                        Err(payload) => failed_capture(project, &test_script, ServerError::RunnerFailed(panic_message(payload))),
+                    };
+                    capture_tx.send(capture).unwrap();
+                }
             });
+        }
+        drop(capture_tx);
+
+        for capture in capture_rx.iter() {
+            let ProjectCapture { name, directory_name, test_data, failures, mismatches, crashes, log, unstable_nodes, rules: rules_config, suppressions } = capture;
+            write_app_log(&log, &directory_name);
+
+            //a project the test runner failed on was not fully tested, even if some screen sizes were captured
+            let runner_failure = failures.iter()
+                .map(|&(_, _, ref e)| e)
+                .find(|e| match *e { &ServerError::RunnerFailed(_) => true, _ => false })
+                .cloned();
+
+            if test_data.len() == 0 {
+                println!("No screen sizes could be captured for {}", name);
+                write_report(&name, None, None, &failures, &mismatches, &crashes, &directory_name, None);
+                junit_test_suites.push(generate_junit_test_suite(&name, &screen_size_names, None, None, &failures, &crashes, None));
+                match runner_failure.or(failures.get(0).map(|&(_, _, ref e)| e.clone())) {
+                    Some(e) => failed_projects.push((name, e)),
+                    None => (),
+                }
+                continue;
+            }
+            if let Some(e) = runner_failure {
+                failed_projects.push((name.clone(), e));
+            }
 
-            let now = now();
-
-            //make sure the output directories exists
-            let mut p = PathBuf::new();
-            p.push("output");
-            let directory_name = format!("{}_{}", &name, now.ctime());
-            let directory_name = directory_name.replace(" ", "_");
-            let directory_name = directory_name.replace(":", "_");
-            println!("DirName: {}", directory_name);
-            p.push(&directory_name);
-            std::fs::create_dir(p);
+            let test_sets = Some(generate_test_sets(test_data, unstable_nodes, tab_stop_merge_threshold(&rules_config))).unwrap();
+            let rules = rule_registry(&rules_config);
+            let (baseline_threshold, overlap_overflow_threshold) = baseline_thresholds(&rules_config, baseline_threshold, overlap_overflow_threshold);
+            let violations = run_all_tests(if save_png { Some(&mut draw_context) } else { None }, &rules, &suppressions, &test_sets, &directory_name, baseline_threshold, overlap_overflow_threshold);
 
-            let tab_stop_merge_threshold = 10.0; //TODO: THIS IS NOT IN USE
-            let test_sets = Some(generate_test_sets(test_data, tab_stop_merge_threshold)).unwrap();
-            let violations = run_all_tests(&mut draw_context, &test_sets, &directory_name, baseline_threshold, overlap_overflow_threshold);
+            write_report(&name, Some(&test_sets), Some(&violations), &failures, &mismatches, &crashes, &directory_name, Some(image_extension));
+            junit_test_suites.push(generate_junit_test_suite(&name, &screen_size_names, Some(&test_sets), Some(&violations), &failures, &crashes, Some((&directory_name, image_extension))));
+            if let Some(fail_on) = fail_on {
+                failing_violations += violations.count_at_least(fail_on);
+            }
 
-            let report = generate_violations_report(&test_sets, &violations);
+            if save_png {
+                draw_context.save_overflow_violations(&test_sets, &violations, &directory_name);
+                draw_context.save_overlap_violations(&test_sets, &violations, &directory_name);
+                draw_context.save_alignment_changed_violations(&test_sets, &violations, &screen_size_names, &directory_name);
+                draw_context.save_custom_violations(&test_sets, &violations, &directory_name);
+            }
+            if save_svg {
+                save_svg_test_sets(&test_sets, &directory_name, svg_tab_stops);
+                save_svg_violations(&test_sets, &violations, &screen_size_names, &directory_name, svg_tab_stops);
+            }
 
-            let report_file_name = format!("./output/{}/report", &directory_name);
-            println!("Report_file_name: {}", report_file_name);
-            let mut file = std::fs::File::create(report_file_name).unwrap();
-            file.write_all(report.as_bytes());
+            if sarif_path.is_some() {
+                sarif_projects.push((name, test_sets, violations));
+            }
+        }
 
-            draw_context.save_overflow_violations(&test_sets, &violations, &directory_name);
-            draw_context.save_overlap_violations(&test_sets, &violations, &directory_name);
-            draw_context.save_alignment_changed_violations(&test_sets, &violations, &directory_name);
+        for &(ref name, ref error) in &failed_projects {
+            println!("Project {} failed: {}", name, error);
+        }
 
-            test_runner_context.kill_process();
-            server.close_current_connection();
+        if let Some(junit_path) = junit_path {
+            write_junit_report(&junit_test_suites, junit_path);
+        }
+        if let Some(sarif_path) = sarif_path {
+            let projects = sarif_projects.iter().map(|&(ref name, ref test_sets, ref violations)| (name.as_str(), test_sets, violations)).collect();
+            write_sarif_report(&projects, sarif_root, sarif_path);
         }
     }
 
+    let code = exit_code(fail_on, failing_violations, failed_projects.len());
+    if code != 0 {
+        std::process::exit(code);
+    }
+
     /*'running: loop {
 
         for event in event_pump.poll_iter() {
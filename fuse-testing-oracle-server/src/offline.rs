@@ -0,0 +1,134 @@
+use std::fs::{self,File};
+use std::io::{self,Read,Write};
+use std::path::{Path,PathBuf};
+
+use serde_json;
+
+use layout_validator::Nodes;
+use server::LayoutChanged;
+use test_sets::TestSetId;
+
+/*A layout payload recorded during an auto run, together with what we need to replay it
+as the same test set: the id it got, and the device and screen size it was captured at.*/
+#[derive(Deserialize)]
+pub struct RecordedLayout {
+    #[serde(rename = "TestSetId")] pub test_set_id: i32,
+    #[serde(rename = "Device")] pub device: String,
+    #[serde(rename = "ScreenSize")] pub screen_size: (i32,i32),
+    #[serde(rename = "Layout")] pub layout: LayoutChanged,
+}
+
+/*Writes the raw json payload, untouched, into <run dir>/layouts.
+The result can be replayed with the offline mode (-l).*/
+pub fn record_layout(directory_name: &str, id: TestSetId, device: &str, screen_size: (i32,i32), json_string: &str) {
+    let mut p = PathBuf::new();
+    p.push("output");
+    p.push(directory_name);
+    p.push("layouts");
+    if let Err(e) = fs::create_dir_all(&p) {
+        println!("Could not create layouts directory {:?}: {}", p, e);
+        return;
+    }
+
+    let device_file_name: String = device.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    p.push(format!("{:03}-{}-{}x{}.json", id.0, device_file_name, screen_size.0, screen_size.1));
+
+    let recorded = format!("{{\"TestSetId\": {}, \"Device\": {}, \"ScreenSize\": [{}, {}], \"Layout\": {}}}",
+                           id.0,
+                           serde_json::to_string(device).unwrap(),
+                           screen_size.0, screen_size.1,
+                           json_string);
+
+    println!("Recording layout to {:?}", p);
+    if let Err(e) = File::create(&p).and_then(|mut f| f.write_all(recorded.as_bytes())) {
+        println!("Could not record layout to {:?}: {}", p, e);
+    }
+}
+
+/*Loads a directory of layouts (one .json file per screen size), so that the validation can be run
+without building and launching the app. Accepts both files written by record_layout and bare
+LayoutChanged payloads. Bare payloads are numbered in file name order, after the ids of the
+recorded ones so the two can't collide, and use the root size as screen size.*/
+pub fn load_test_data_from_dir(dir: &Path) -> io::Result<Vec<(TestSetId, Nodes, (i32,i32))>> {
+    println!("Loading recorded layouts from {:?}", dir);
+
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().map_or(false, |ext| ext == "json"))
+        .collect();
+    paths.sort();
+
+    let mut test_data = Vec::new();
+    let mut bare_layouts = Vec::new();
+    for path in paths {
+        let mut json_string = String::new();
+        if let Err(e) = File::open(&path).and_then(|mut f| f.read_to_string(&mut json_string)) {
+            println!("Skipping {:?}, could not read it: {}", path, e);
+            continue;
+        }
+
+        if let Ok(recorded) = serde_json::from_str::<RecordedLayout>(&json_string) {
+            println!("Loaded {:?} recorded on {} ({:?})", path, recorded.device, recorded.screen_size);
+            test_data.push((TestSetId(recorded.test_set_id), recorded.layout.to_nodes(), recorded.screen_size));
+            continue;
+        }
+
+        match LayoutChanged::from_json_string(&json_string) {
+            Ok(layout_changed) => {
+                let screen_size = layout_changed.root_size();
+                println!("Loaded {:?} with screen size {:?}", path, screen_size);
+                bare_layouts.push((layout_changed.to_nodes(), screen_size));
+            },
+            Err(e) => {
+                println!("Skipping {:?}, not a valid layout: {}", path, e);
+            }
+        }
+    }
+
+    let first_bare_id = test_data.iter().map(|&(id, _, _)| id.0 + 1).max().unwrap_or(0);
+    for (i, (nodes, screen_size)) in bare_layouts.into_iter().enumerate() {
+        test_data.push((TestSetId(first_bare_id + i as i32), nodes, screen_size));
+    }
+
+    Ok(test_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn layout(width: i32, height: i32) -> String {
+        format!("{{\"Id\": 0, \"ScreenSize\": {{\"W\": {}, \"H\": {}}}, \"Nodes\": {{\"Children\": [], \"Name\": \"Root\", \"Line\": 1, \"File\": \"MainView.ux\", \
+                 \"ActualPositionX\": 0, \"ActualPositionY\": 0, \"ActualWidth\": {}, \"ActualHeight\": {}, \
+                 \"RenderWidth\": {}, \"RenderHeight\": {}, \"RenderPositionX\": 0, \"RenderPositionY\": 0}}}}",
+                width, height, width, height, width, height)
+    }
+
+    #[test]
+    fn bare_layouts_are_numbered_after_recorded_ones() {
+        let dir = env::temp_dir().join("fuse-oracle-offline-test");
+        fs::create_dir_all(&dir).unwrap();
+        let files = vec![
+            ("000-bare.json", layout(320, 568)),
+            ("001-phone.json", format!("{{\"TestSetId\": 1, \"Device\": \"phone\", \"ScreenSize\": [750, 1334], \"Layout\": {}}}", layout(375, 667))),
+            ("002-bare.json", layout(1024, 768)),
+        ];
+        for &(name, ref contents) in &files {
+            File::create(dir.join(name)).and_then(|mut f| f.write_all(contents.as_bytes())).unwrap();
+        }
+
+        let test_data = load_test_data_from_dir(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+        let ids: Vec<(i32, (i32,i32))> = test_data.unwrap().iter().map(|&(id, _, screen_size)| (id.0, screen_size)).collect();
+        assert_eq!(ids, vec![(1, (750, 1334)), (2, (320, 568)), (3, (1024, 768))]);
+    }
+
+    #[test]
+    fn missing_directory_is_an_error() {
+        assert!(load_test_data_from_dir(Path::new("no/such/layouts")).is_err());
+    }
+}
@@ -0,0 +1,109 @@
+/*The handshake done when an app connects. The app sends a Hello with the newest protocol version
+it speaks and the capabilities it has, the server answers with a Welcome that either rejects it,
+or tells it which version and capabilities will be used for the rest of the connection.*/
+
+pub const PROTOCOL_VERSION: i32 = 1;
+//clients older than this are rejected
+pub const MIN_PROTOCOL_VERSION: i32 = 1;
+
+/*The test runner starts each app with the key of the session that waits for it in this environment
+variable, and the app passes it back in its Hello. That is how the server knows which app is which
+when several are tested at once. An app that doesn't send a key only gets a session if just one
+is waiting, with more it is rejected since we can't tell which app it is.*/
+pub const SESSION_ENV_VAR: &'static str = "FUSE_ORACLE_SESSION";
+
+pub const CAPABILITY_RESIZE_WINDOW: &'static str = "ResizeWindow";
+//the app can tell when its layout has stopped changing, see Session::wait_for_layout_ready
+pub const CAPABILITY_LAYOUT_READY: &'static str = "LayoutReady";
+
+/*The app echoes the heartbeats the server sends. An app that goes quiet for too long is
+considered hung and is disconnected, which makes the test runner restart it.*/
+pub const CAPABILITY_HEARTBEAT: &'static str = "Heartbeat";
+
+pub const SERVER_CAPABILITIES: &'static [&'static str] = &[CAPABILITY_RESIZE_WINDOW, CAPABILITY_LAYOUT_READY, CAPABILITY_HEARTBEAT];
+//the test runner can't do its job without these
+pub const REQUIRED_CAPABILITIES: &'static [&'static str] = &[CAPABILITY_RESIZE_WINDOW];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Hello {
+    #[serde(rename = "ProtocolVersion")] pub protocol_version: i32,
+    #[serde(rename = "Capabilities", default)] pub capabilities: Vec<String>,
+    #[serde(rename = "Session", default)] pub session: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Welcome {
+    #[serde(rename = "Accepted")] pub accepted: bool,
+    #[serde(rename = "ProtocolVersion")] pub protocol_version: i32,
+    #[serde(rename = "Capabilities")] pub capabilities: Vec<String>,
+    #[serde(rename = "Reason", default)] pub reason: Option<String>,
+}
+
+//what was agreed on with the connected client
+#[derive(Debug, Clone)]
+pub struct ClientInfo {
+    pub protocol_version: i32,
+    pub capabilities: Vec<String>,
+}
+
+impl ClientInfo {
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+impl Hello {
+    pub fn new(session: Option<String>) -> Hello {
+        Hello {
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: SERVER_CAPABILITIES.iter().map(|c| c.to_string()).collect(),
+            session: session,
+        }
+    }
+}
+
+impl Welcome {
+    pub fn accept(client_info: &ClientInfo) -> Welcome {
+        Welcome {
+            accepted: true,
+            protocol_version: client_info.protocol_version,
+            capabilities: client_info.capabilities.clone(),
+            reason: None,
+        }
+    }
+
+    pub fn reject(reason: String) -> Welcome {
+        Welcome {
+            accepted: false,
+            protocol_version: PROTOCOL_VERSION,
+            capabilities: Vec::new(),
+            reason: Some(reason),
+        }
+    }
+}
+
+/*Newer clients are downgraded to our version, and only the capabilities both sides have are used.*/
+pub fn negotiate(hello: &Hello) -> Result<ClientInfo, String> {
+    if hello.protocol_version < MIN_PROTOCOL_VERSION {
+        return Err(format!("protocol version {} is too old, the server needs at least {}",
+                           hello.protocol_version, MIN_PROTOCOL_VERSION));
+    }
+
+    let capabilities: Vec<String> = SERVER_CAPABILITIES.iter()
+        .filter(|c| hello.capabilities.iter().any(|hc| hc == *c))
+        .map(|c| c.to_string())
+        .collect();
+
+    for required in REQUIRED_CAPABILITIES {
+        if !capabilities.iter().any(|c| c == required) {
+            return Err(format!("client is missing the {} capability", required));
+        }
+    }
+
+    let protocol_version = if hello.protocol_version > PROTOCOL_VERSION { PROTOCOL_VERSION } else { hello.protocol_version };
+
+    Ok(ClientInfo {
+        protocol_version: protocol_version,
+        capabilities: capabilities,
+    })
+}
@@ -0,0 +1,391 @@
+use serde_json;
+use sdl2::rect::Rect;
+use std::env;
+use std::fs;
+use normalize_path;
+use test_sets::*;
+use layout_validator::validity_rules::{LayoutViolation,LayoutViolations};
+use layout_validator::rules::Severity;
+use layout_validator::overlap_and_overflow::JsonRect;
+
+const SARIF_SCHEMA: &'static str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+//the built in rules, custom rules are described by their kind only
+const RULE_DESCRIPTIONS: &'static [(&'static str, &'static str)] = &[
+    ("Overlap", "Two sibling elements overlap"),
+    ("Overflow", "An element sticks out of its parent"),
+    ("AlignmentLost", "Two elements that are aligned on most screen sizes are not aligned on some"),
+    ("UnstableLayout", "An element moves between captures of the same screen size"),
+];
+
+#[derive(Serialize)]
+pub struct SarifMessage {
+    #[serde(rename = "text")] pub text: String,
+}
+
+#[derive(Serialize)]
+pub struct SarifArtifactLocation {
+    #[serde(rename = "uri")] pub uri: String,
+    #[serde(rename = "uriBaseId", skip_serializing_if = "Option::is_none")] pub uri_base_id: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SarifRegion {
+    #[serde(rename = "startLine")] pub start_line: i32,
+}
+
+#[derive(Serialize)]
+pub struct SarifPhysicalLocation {
+    #[serde(rename = "artifactLocation")] pub artifact_location: SarifArtifactLocation,
+    //left out when the app did not know the line of the node
+    #[serde(rename = "region", skip_serializing_if = "Option::is_none")] pub region: Option<SarifRegion>,
+}
+
+#[derive(Serialize)]
+pub struct SarifLocation {
+    #[serde(rename = "physicalLocation")] pub physical_location: SarifPhysicalLocation,
+    //the name of the node at this location
+    #[serde(rename = "message")] pub message: SarifMessage,
+}
+
+#[derive(Serialize)]
+pub struct SarifSuppression {
+    //external, as they live in the test script and not in the ux files
+    #[serde(rename = "kind")] pub kind: &'static str,
+    #[serde(rename = "justification")] pub justification: String,
+}
+
+#[derive(Serialize)]
+pub struct SarifScreenSize {
+    #[serde(rename = "testSet")] pub test_set: TestSetId,
+    #[serde(rename = "width")] pub width: i32,
+    #[serde(rename = "height")] pub height: i32,
+}
+
+#[derive(Serialize)]
+pub struct SarifResultProperties<'a> {
+    #[serde(rename = "project")] pub project: String,
+    //the screen sizes the violation is found on
+    #[serde(rename = "screenSizes")] pub screen_sizes: Vec<SarifScreenSize>,
+    //everything the violation knows, its rects included, as in report.json
+    #[serde(rename = "violation")] pub violation: &'a LayoutViolation,
+    #[serde(rename = "rects")] pub rects: Vec<JsonRect<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct SarifResult<'a> {
+    #[serde(rename = "ruleId")] pub rule_id: String,
+    #[serde(rename = "level")] pub level: &'static str,
+    #[serde(rename = "message")] pub message: SarifMessage,
+    //a location for each node of the violation, in the order of LayoutViolation::nodes
+    #[serde(rename = "locations")] pub locations: Vec<SarifLocation>,
+    #[serde(rename = "suppressions", skip_serializing_if = "Vec::is_empty")] pub suppressions: Vec<SarifSuppression>,
+    #[serde(rename = "properties")] pub properties: SarifResultProperties<'a>,
+}
+
+#[derive(Serialize)]
+pub struct SarifRule {
+    #[serde(rename = "id")] pub id: String,
+    #[serde(rename = "shortDescription")] pub short_description: SarifMessage,
+}
+
+#[derive(Serialize)]
+pub struct SarifDriver {
+    #[serde(rename = "name")] pub name: &'static str,
+    #[serde(rename = "rules")] pub rules: Vec<SarifRule>,
+}
+
+#[derive(Serialize)]
+pub struct SarifTool {
+    #[serde(rename = "driver")] pub driver: SarifDriver,
+}
+
+#[derive(Serialize)]
+pub struct SarifUriBase {
+    #[serde(rename = "uri")] pub uri: String,
+}
+
+#[derive(Serialize)]
+pub struct SarifRun<'a> {
+    #[serde(rename = "tool")] pub tool: SarifTool,
+    #[serde(rename = "originalUriBaseIds", skip_serializing_if = "Option::is_none")] pub original_uri_base_ids: Option<SarifUriBaseIds>,
+    #[serde(rename = "results")] pub results: Vec<SarifResult<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct SarifUriBaseIds {
+    #[serde(rename = "SRCROOT")] pub source_root: SarifUriBase,
+}
+
+#[derive(Serialize)]
+pub struct SarifLog<'a> {
+    #[serde(rename = "$schema")] pub schema: &'static str,
+    #[serde(rename = "version")] pub version: &'static str,
+    #[serde(rename = "runs")] pub runs: Vec<SarifRun<'a>>,
+}
+
+fn sarif_level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Info => "note",
+    }
+}
+
+//percent-encodes everything in the path segments but the characters that are always safe in a uri
+fn encode_path(path: &str) -> String {
+    let mut encoded = String::new();
+    for &b in path.as_bytes() {
+        match b {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => encoded.push(b as char),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+//path is absolute, the drive letter of a windows path is kept as it is
+fn file_uri(path: &str) -> String {
+    if path.starts_with("/") {
+        format!("file://{}", encode_path(path))
+    } else {
+        format!("file:///{}{}", &path[..2], encode_path(&path[2..]))
+    }
+}
+
+fn is_absolute(path: &str) -> bool {
+    let bytes = path.as_bytes();
+    path.starts_with("/") || (bytes.len() > 2 && bytes[1] == b':' && bytes[2] == b'/')
+}
+
+/*The root as an absolute path with forward slashes and no trailing slash, so it can be compared
+with the ux file paths and given as a file uri. A root that does not exist here is used as is,
+the app may have been built on another machine.*/
+fn absolute_source_root(source_root: &str) -> String {
+    let normalized = normalize_path(source_root);
+    let root = match fs::canonicalize(source_root) {
+        Ok(path) => normalize_path(&path.to_string_lossy()),
+        Err(_) if is_absolute(&normalized) => normalized,
+        Err(_) => normalize_path(&env::current_dir().unwrap().join(source_root).to_string_lossy()),
+    };
+    //canonicalize gives \\?\C:\... on windows
+    root.trim_left_matches("//?/").trim_right_matches('/').to_string()
+}
+
+//what is left of file after prefix, paths only differ in case on windows
+fn strip_prefix<'a>(file: &'a str, prefix: &str) -> Option<&'a str> {
+    if !file.is_char_boundary(prefix.len()) {
+        return None;
+    }
+    let (head, rest) = file.split_at(prefix.len());
+    let matches = if cfg!(windows) { head.to_lowercase() == prefix.to_lowercase() } else { head == prefix };
+    if matches { Some(rest) } else { None }
+}
+
+/*The ux files are reported by the app with the paths they were built from. Files under
+source_root (from absolute_source_root) are made relative to it, so the viewer can find them
+in its checkout.*/
+fn artifact_location(file: &str, source_root: Option<&str>) -> SarifArtifactLocation {
+    let file = normalize_path(file);
+    if let Some(source_root) = source_root {
+        if let Some(relative) = strip_prefix(&file, &format!("{}/", source_root)) {
+            return SarifArtifactLocation {
+                uri: encode_path(relative),
+                uri_base_id: Some("SRCROOT".to_string()),
+            };
+        }
+    }
+    SarifArtifactLocation {
+        uri: if is_absolute(&file) { file_uri(&file) } else { encode_path(&file) },
+        uri_base_id: None,
+    }
+}
+
+fn violation_rects(violation: &LayoutViolation) -> Vec<Rect> {
+    match violation {
+        &LayoutViolation::Overlap { intersection_rect, .. } => vec![intersection_rect],
+        &LayoutViolation::Overflow { ref overflow_rect, .. } => overflow_rect.rects(),
+        &LayoutViolation::AlignmentLost { .. } => Vec::new(),
+        &LayoutViolation::UnstableLayout { ref rects, .. } => rects.clone(),
+        &LayoutViolation::Custom(ref custom) => custom.rects.clone(),
+    }
+}
+
+fn sarif_result<'a>(project_name: &str,
+                    test_sets: &TestSets,
+                    violation: &'a LayoutViolation,
+                    rects: &'a Vec<Rect>,
+                    suppression: Option<String>,
+                    source_root: Option<&str>) -> SarifResult<'a> {
+    let violation_test_sets: Vec<&TestSet> = violation.test_sets().iter()
+        .filter_map(|id| test_sets.sets.get(id))
+        .collect();
+
+    //a node is the same in all the test sets, so the first one that has it is used
+    let locations = violation.nodes().iter().filter_map(|id| {
+        violation_test_sets.iter().filter_map(|ts| ts.nodes.get_from_id(*id)).next()
+    }).map(|node| {
+        SarifLocation {
+            physical_location: SarifPhysicalLocation {
+                artifact_location: artifact_location(&node.node_data.file, source_root),
+                region: if node.node_data.line > 0 { Some(SarifRegion { start_line: node.node_data.line }) } else { None },
+            },
+            message: SarifMessage { text: node.node_data.name.clone() },
+        }
+    }).collect();
+
+    let failing = violation.failing_test_sets(test_sets);
+    let text = match failing.get(0).or(violation.test_sets().get(0)).and_then(|id| test_sets.sets.get(id)) {
+        Some(ts) => violation.describe(ts),
+        None => format!("{}", violation),
+    };
+
+    SarifResult {
+        rule_id: violation.kind().to_string(),
+        level: sarif_level(violation.severity()),
+        message: SarifMessage { text: text },
+        locations: locations,
+        suppressions: match suppression {
+            Some(justification) => vec![SarifSuppression { kind: "external", justification: justification }],
+            None => Vec::new(),
+        },
+        properties: SarifResultProperties {
+            project: project_name.to_string(),
+            screen_sizes: failing.iter().filter_map(|id| test_sets.sets.get(id)).map(|ts| {
+                SarifScreenSize {
+                    test_set: ts.id,
+                    width: ts.screen_size.0,
+                    height: ts.screen_size.1,
+                }
+            }).collect(),
+            violation: violation,
+            rects: rects.iter().map(|r| JsonRect(r)).collect(),
+        },
+    }
+}
+
+/*A SARIF 2.1 log with one run for all the projects. Each violation is a result whose rule id is
+its kind, located at the ux file and line of each of its nodes. Baselined and suppressed
+violations are included as suppressed results, so viewers can hide them.
+source_root is the directory the ux file paths are made relative to.*/
+pub fn generate_sarif_report(projects: &Vec<(&str, &TestSets, &LayoutViolations)>, source_root: Option<&str>) -> String {
+    let source_root = source_root.map(absolute_source_root);
+    let source_root = source_root.as_ref().map(|s| s.as_str());
+
+    //the rects are computed up front, the results borrow them
+    let mut entries: Vec<(&str, &TestSets, &LayoutViolation, Option<String>, Vec<Rect>)> = Vec::new();
+    for &(name, test_sets, violations) in projects {
+        let reported = violations.overflows.iter()
+            .chain(violations.overlaps.iter())
+            .chain(violations.alignment_changes.iter())
+            .chain(violations.unstable_layouts.iter())
+            .chain(violations.custom.iter());
+        for v in reported {
+            entries.push((name, test_sets, v, None, violation_rects(v)));
+        }
+        for v in &violations.baselined {
+            entries.push((name, test_sets, v, Some("Part of the baseline, found on most screen sizes".to_string()), violation_rects(v)));
+        }
+        for &(ref v, ref suppression) in &violations.suppressed {
+            entries.push((name, test_sets, v, Some(format!("Matches the suppression {} in the test script", suppression)), violation_rects(v)));
+        }
+    }
+
+    let results: Vec<SarifResult> = entries.iter().map(|&(name, test_sets, v, ref suppression, ref rects)| {
+        sarif_result(name, test_sets, v, rects, suppression.clone(), source_root)
+    }).collect();
+
+    let mut rules: Vec<SarifRule> = RULE_DESCRIPTIONS.iter().map(|&(id, description)| {
+        SarifRule {
+            id: id.to_string(),
+            short_description: SarifMessage { text: description.to_string() },
+        }
+    }).collect();
+    for result in &results {
+        if !rules.iter().any(|r| r.id == result.rule_id) {
+            rules.push(SarifRule {
+                id: result.rule_id.clone(),
+                short_description: SarifMessage { text: format!("Reported by the {} rule", result.rule_id) },
+            });
+        }
+    }
+
+    let log = SarifLog {
+        schema: SARIF_SCHEMA,
+        version: "2.1.0",
+        runs: vec![SarifRun {
+            tool: SarifTool {
+                driver: SarifDriver {
+                    name: "fuse-testing-oracle-server",
+                    rules: rules,
+                },
+            },
+            original_uri_base_ids: source_root.map(|source_root| {
+                SarifUriBaseIds {
+                    source_root: SarifUriBase { uri: format!("{}/", file_uri(source_root)) },
+                }
+            }),
+            results: results,
+        }],
+    };
+
+    serde_json::to_string_pretty(&log).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_under_the_root_are_relative() {
+        let location = artifact_location("/work/app/Pages\\Main.ux", Some("/work/app"));
+        assert_eq!(location.uri, "Pages/Main.ux");
+        assert_eq!(location.uri_base_id, Some("SRCROOT".to_string()));
+
+        let location = artifact_location("/work/other/Main.ux", Some("/work/app"));
+        assert_eq!(location.uri, "file:///work/other/Main.ux");
+        assert_eq!(location.uri_base_id, None);
+    }
+
+    #[test]
+    fn prefix_ending_inside_a_character() {
+        //the length of "/x/" ends in the middle of the é
+        let location = artifact_location("/aé", Some("/x"));
+        assert_eq!(location.uri_base_id, None);
+        let location = artifact_location("/w", Some("/work/app"));
+        assert_eq!(location.uri, "file:///w");
+    }
+
+    #[test]
+    fn uris_are_percent_encoded() {
+        let location = artifact_location("/work/app/My Pages/#1 100%.ux", Some("/work/app"));
+        assert_eq!(location.uri, "My%20Pages/%231%20100%25.ux");
+
+        let location = artifact_location("C:\\My Work\\Main.ux", None);
+        assert_eq!(location.uri, "file:///C:/My%20Work/Main.ux");
+
+        let location = artifact_location("/work/aé?.ux", None);
+        assert_eq!(location.uri, "file:///work/a%C3%A9%3F.ux");
+
+        assert_eq!(file_uri("/work/my app"), "file:///work/my%20app");
+    }
+
+    #[test]
+    fn case_only_matters_outside_windows() {
+        let location = artifact_location("C:\\Work\\App\\Main.ux", Some("C:/work/app"));
+        if cfg!(windows) {
+            assert_eq!(location.uri, "Main.ux");
+        } else {
+            assert_eq!(location.uri, "file:///C:/Work/App/Main.ux");
+        }
+    }
+
+    #[test]
+    fn relative_root_is_made_absolute() {
+        let root = absolute_source_root(".");
+        assert!(is_absolute(&root), "{}", root);
+        assert!(!root.ends_with("/"));
+        assert_eq!(absolute_source_root("/not/here/"), "/not/here");
+        assert_eq!(absolute_source_root("D:\\not\\here"), "D:/not/here");
+    }
+}
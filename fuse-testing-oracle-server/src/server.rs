@@ -2,40 +2,67 @@ use std::clone::Clone;
 use std::io;
 use std::sync::mpsc::{Sender,Receiver,channel};
 use std::sync::{Arc,Mutex};
-//use mio::*;
-//use mio::tcp::{TcpListener, TcpStream};
-use std::net::*;
+use std::sync::atomic::{AtomicUsize,Ordering};
+use mio::{Poll,Events,Token,Ready,PollOpt,Registration,SetReadiness};
+use mio::net::{TcpListener,TcpStream};
+use std::net::SocketAddr;
 use std::io::{Read,Write};
 use super::{Message, MessageType, MessageData};
-use super::TestInstruction;
+use super::codec::MessageCodec;
+use super::app_log::{AppLog,LogSource};
+use super::protocol::{self, Hello, Welcome, ClientInfo, CAPABILITY_LAYOUT_READY, CAPABILITY_HEARTBEAT};
 use super::serde_json;
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell,RefCell};
 use std::ops::Deref;
+use std::mem;
 use rand::Rng;
 use super::random_color::*;
 use super::layout_validator::*;
 use std::collections::{HashSet,HashMap};
-use std::thread::*;
-use std::time::Duration;
+use std::thread::{spawn,sleep};
+use std::time::{Duration,Instant};
+use std::sync::mpsc::RecvTimeoutError;
+use std::error::Error;
+use std::fmt;
+use std::cmp::min;
 
 #[derive(Serialize,Deserialize,Hash,Eq,PartialEq,Debug)]
 pub struct JSONNode {
-    #[serde(rename = "Children")] children: Vec<JSONNode>,
-    #[serde(rename = "Name")] name: String,
-    #[serde(rename = "Line")] line: i32,
-    #[serde(rename = "File")] file: String,
-    #[serde(rename = "ActualPositionX")] actual_position_x: i32,
-    #[serde(rename = "ActualPositionY")] actual_position_y: i32,
-    #[serde(rename = "ActualWidth")] actual_width: i32,
-    #[serde(rename = "ActualHeight")] actual_height: i32,
-    #[serde(rename = "RenderWidth")] render_width: i32,
-    #[serde(rename = "RenderHeight")] render_height: i32,
-    #[serde(rename = "RenderPositionX")] render_position_x: i32,
-    #[serde(rename = "RenderPositionY")] render_position_y: i32,
+    #[serde(rename = "Children")] pub children: Vec<JSONNode>,
+    #[serde(rename = "Name")] pub name: String,
+    #[serde(rename = "Line")] pub line: i32,
+    #[serde(rename = "File")] pub file: String,
+    #[serde(rename = "ActualPositionX")] pub actual_position_x: i32,
+    #[serde(rename = "ActualPositionY")] pub actual_position_y: i32,
+    #[serde(rename = "ActualWidth")] pub actual_width: i32,
+    #[serde(rename = "ActualHeight")] pub actual_height: i32,
+    #[serde(rename = "RenderWidth")] pub render_width: i32,
+    #[serde(rename = "RenderHeight")] pub render_height: i32,
+    #[serde(rename = "RenderPositionX")] pub render_position_x: i32,
+    #[serde(rename = "RenderPositionY")] pub render_position_y: i32,
 }
 
 impl JSONNode {
+    //a node that is rendered where it is laid out, like one without transforms
+    pub fn new(name: &str, file: &str, line: i32, rect: (i32,i32,i32,i32), children: Vec<JSONNode>) -> JSONNode {
+        let (x, y, width, height) = rect;
+        JSONNode {
+            children: children,
+            name: name.to_string(),
+            line: line,
+            file: file.to_string(),
+            actual_position_x: x,
+            actual_position_y: y,
+            actual_width: width,
+            actual_height: height,
+            render_width: width,
+            render_height: height,
+            render_position_x: x,
+            render_position_y: y,
+        }
+    }
+
     fn into_validator_node_impl(&self, parent: Option<i32>, nodes: Rc<RefCell<Vec<Node>>>) {
         let mut id;
         {
@@ -78,209 +105,931 @@ impl JSONNode {
     }
 }
 
-#[derive(Deserialize,Debug)]
+#[derive(Serialize,Deserialize,Debug)]
 pub struct ScreenSize {
     #[serde(rename = "W")] w: f32,
     #[serde(rename = "H")] h: f32,
 }
 
-#[derive(Deserialize)]
+impl ScreenSize {
+    pub fn new(w: f32, h: f32) -> ScreenSize {
+        ScreenSize {
+            w: w,
+            h: h,
+        }
+    }
+
+    pub fn size(&self) -> (i32,i32) {
+        (self.w as i32, self.h as i32)
+    }
+}
+
+#[derive(Serialize,Deserialize)]
 pub struct LayoutChanged {
     #[serde(rename = "Id")] id: i32,
     #[serde(rename = "ScreenSize")] pub screen_size: ScreenSize,
     #[serde(rename = "Nodes")] pub nodes: JSONNode,
 }
 
-fn parse_json(json_string: &str) -> LayoutChanged {
+impl LayoutChanged {
+    pub fn new(id: i32, screen_size: ScreenSize, nodes: JSONNode) -> LayoutChanged {
+        LayoutChanged {
+            id: id,
+            screen_size: screen_size,
+            nodes: nodes,
+        }
+    }
+
+    pub fn from_json_string(json_string: &str) -> serde_json::Result<LayoutChanged> {
+        serde_json::from_str(json_string)
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    pub fn root_size(&self) -> (i32,i32) {
+        self.screen_size.size()
+    }
+
+    pub fn to_nodes(&self) -> Nodes {
+        let validator_nodes = self.nodes.into_validator_node();
+        Nodes::new(validator_nodes, self.root_size())
+    }
+}
+
+fn parse_json(json_string: &str) -> Option<LayoutChanged> {
     println!("JSON: {}", json_string);
-    serde_json::from_str(json_string).unwrap()
+    match LayoutChanged::from_json_string(json_string) {
+        Ok(layout_changed) => Some(layout_changed),
+        Err(e) => {
+            println!("Discarding layout data that could not be parsed: {}", e);
+            None
+        }
+    }
+}
+
+//the app's answer to a layout ready request
+#[derive(Serialize,Deserialize,Debug)]
+struct LayoutReady {
+    #[serde(rename = "Id")] id: i32,
+}
+
+//something the app wants in its log, see Session::set_log
+#[derive(Serialize,Deserialize,Debug)]
+struct ClientLog {
+    #[serde(rename = "Message")] message: String,
 }
 
-pub struct ServerBuffer {
-    buffer: Vec<u8>,
+#[derive(Debug,Clone)]
+pub enum ServerError {
+    //the app did not answer in time, the string says what we were waiting for
+    Timeout(&'static str),
+    //the app closed the connection (or crashed) while we were waiting for it
+    ClientDisconnected,
+    //the server thread is gone
+    ServerStopped,
+    //the test runner itself failed, like when the app could not be built or started
+    RunnerFailed(String),
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &ServerError::Timeout(operation) => write!(f, "timed out waiting for {}", operation),
+            &ServerError::ClientDisconnected => write!(f, "the app disconnected"),
+            &ServerError::ServerStopped => write!(f, "the server thread stopped"),
+            &ServerError::RunnerFailed(ref message) => write!(f, "the test runner failed: {}", message),
+        }
+    }
+}
+
+impl Error for ServerError {
+    fn description(&self) -> &str {
+        match self {
+            &ServerError::Timeout(_) => "timed out",
+            &ServerError::ClientDisconnected => "the app disconnected",
+            &ServerError::ServerStopped => "the server thread stopped",
+            &ServerError::RunnerFailed(_) => "the test runner failed",
+        }
+    }
+}
+
+/*A layout that did not answer the request we were waiting for. It is discarded,
+since filing it under the current screen size would mix up the test sets.*/
+#[derive(Debug,Clone)]
+pub enum LayoutMismatch {
+    //the answer to an earlier request, most likely one that timed out
+    Stale { expected: i32, got: i32 },
+    //a layout with an id we never asked for (the app sends -1 for layouts it sends on its own)
+    Unsolicited(i32),
+}
+
+impl fmt::Display for LayoutMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &LayoutMismatch::Stale { expected, got } => write!(f, "stale layout for request {} while waiting for request {}", got, expected),
+            &LayoutMismatch::Unsolicited(id) => write!(f, "unsolicited layout with id {}", id),
+        }
+    }
+}
+
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 60;
+const DEFAULT_RESPONSE_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_LAYOUT_READY_TIMEOUT_SECS: u64 = 5;
+//how often wait_for_client_while checks on the app
+const APP_RUNNING_CHECK_MS: u64 = 500;
+
+const LISTENER: Token = Token(0);
+const COMMANDS: Token = Token(1);
+//connections get the tokens from here and up
+const FIRST_CLIENT: usize = 2;
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+//how often connected apps are sent a heartbeat, this is also how often the server thread wakes up on its own
+const HEARTBEAT_INTERVAL_MS: u64 = 2000;
+//an app that has not sent us anything for this long is considered hung and is disconnected
+const HEARTBEAT_TIMEOUT_SECS: u64 = 15;
+
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub struct SessionId(pub usize);
+
+/*Commands go to the server thread over a channel, and setting the readiness wakes up its poll,
+so the thread sleeps until it either has socket data or a command to handle.*/
+#[derive(Clone)]
+struct CommandSender {
+    sender: Sender<(SessionId, ServerCommand)>,
+    set_readiness: SetReadiness,
+}
+
+impl CommandSender {
+    fn send(&self, session: SessionId, command: ServerCommand) {
+        self.sender.send((session, command));
+        self.set_readiness.set_readiness(Ready::readable());
+    }
 }
 
-impl ServerBuffer {
-    fn new() -> ServerBuffer {
-        ServerBuffer {
-            buffer: Vec::new()
+//a session that was created, but whose app has not connected yet
+struct WaitingSession {
+    id: SessionId,
+    events: Sender<ServerCommand>,
+    //where the app's Log messages go
+    log: Option<AppLog>,
+}
+
+//a connected app, as seen from the server thread
+struct Connection {
+    stream: TcpStream,
+    codec: MessageCodec,
+    //bytes the socket was not ready to take yet
+    outgoing: Vec<u8>,
+    //the client is only bound to a session once it has sent a Hello we accept
+    client_info: Option<ClientInfo>,
+    session: Option<WaitingSession>,
+    //requests sent on this connection that have not been answered yet
+    pending_requests: HashSet<i32>,
+    //any message from the app shows that it is alive, not only the heartbeat answers
+    last_received: Instant,
+    last_heartbeat: Instant,
+    next_heartbeat: i32,
+}
+
+impl Connection {
+    fn new(stream: TcpStream) -> Connection {
+        Connection {
+            stream: stream,
+            codec: MessageCodec::new(),
+            outgoing: Vec::new(),
+            client_info: None,
+            session: None,
+            pending_requests: HashSet::new(),
+            last_received: Instant::now(),
+            last_heartbeat: Instant::now(),
+            next_heartbeat: 0,
+        }
+    }
+
+    fn session_id(&self) -> Option<SessionId> {
+        self.session.as_ref().map(|s| s.id)
+    }
+
+    fn send_event(&self, command: ServerCommand) {
+        if let Some(ref session) = self.session {
+            session.events.send(command);
+        }
+    }
+
+    fn interest(&self) -> Ready {
+        if self.outgoing.len() > 0 {
+            Ready::readable() | Ready::writable()
+        } else {
+            Ready::readable()
+        }
+    }
+
+    fn send_message(&mut self, message_type: MessageType, data: String) {
+        let message = Message {
+            message_type: message_type,
+            length: data.len() as i32,
+            data: MessageData {
+                json_string: data
+            },
+        };
+        self.outgoing.extend_from_slice(message.as_bytes().as_slice());
+    }
+
+    //writes as much as the socket takes, returns false if the connection is broken
+    fn flush(&mut self) -> bool {
+        while self.outgoing.len() > 0 {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return false,
+                Ok(bytes_written) => {
+                    self.outgoing.drain(0..bytes_written);
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    println!("Could not write to the client: {}", e);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    //reads until the socket is drained (the poll is edge triggered), returns false if the connection is closed
+    fn read(&mut self, bytes: &mut [u8]) -> bool {
+        loop {
+            match self.stream.read(bytes) {
+                Ok(0) => {
+                    println!("The client closed the connection");
+                    return false;
+                },
+                Ok(bytes_read) => {
+                    self.last_received = Instant::now();
+                    self.codec.add_to_buffer(&bytes[0..bytes_read]);
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    println!("Could not read from the client: {}", e);
+                    return false;
+                }
+            }
+        }
+    }
+
+    /*Finds the session the client asks for. A client that does not ask for one only gets the
+    waiting session if there is just one, with more we can't tell which app it is.*/
+    fn claim_session(hello: &Hello, waiting_sessions: &mut Vec<WaitingSession>) -> Result<WaitingSession, String> {
+        let index = match hello.session {
+            Some(ref key) => {
+                let id = key.parse::<usize>().ok().map(SessionId);
+                match waiting_sessions.iter().position(|s| Some(s.id) == id) {
+                    Some(index) => index,
+                    None => return Err(format!("no session with key {:?} is waiting for an app", key)),
+                }
+            },
+            None => {
+                match waiting_sessions.len() {
+                    0 => return Err("no session is waiting for an app".to_string()),
+                    1 => 0,
+                    n => return Err(format!("{} sessions are waiting for an app and the client sent no session key", n)),
+                }
+            }
+        };
+        Ok(waiting_sessions.remove(index))
+    }
+
+    //returns false if the connection should be closed
+    fn handle_messages(&mut self, waiting_sessions: &mut Vec<WaitingSession>) -> bool {
+        while let Some(decoded) = self.codec.decode() {
+            let message = match decoded {
+                Ok(message) => message,
+                Err(e) => {
+                    println!("Protocol error from client: {}", e);
+                    if e.is_recoverable() {
+                        continue;
+                    }
+                    println!("Closing the connection, the stream is out of sync");
+                    return false;
+                }
+            };
+            if self.client_info.is_none() {
+                match message.message_type {
+                    MessageType::Hello => (),
+                    _ => {
+                        println!("Ignoring {:?} message, the client has not sent a Hello yet", message.message_type);
+                        continue;
+                    }
+                }
+            }
+            match message.message_type {
+                MessageType::Hello => {
+                    if self.client_info.is_some() {
+                        println!("Ignoring Hello, the handshake is already done");
+                        continue;
+                    }
+                    let negotiated = match serde_json::from_str::<Hello>(&message.data.json_string) {
+                        Ok(hello) => protocol::negotiate(&hello).and_then(|info| {
+                            Connection::claim_session(&hello, waiting_sessions).map(|session| (info, session))
+                        }),
+                        Err(e) => Err(format!("could not parse Hello: {}", e)),
+                    };
+                    match negotiated {
+                        Ok((info, session)) => {
+                            println!("Client accepted into session {}, protocol version {}, capabilities {:?}",
+                                     session.id.0, info.protocol_version, info.capabilities);
+                            let welcome = serde_json::to_string(&Welcome::accept(&info)).unwrap();
+                            self.send_message(MessageType::Welcome, welcome);
+                            self.client_info = Some(info.clone());
+                            self.session = Some(session);
+                            self.send_event(ServerCommand::ClientConnected(info));
+                        },
+                        Err(reason) => {
+                            println!("Rejecting client: {}", reason);
+                            let welcome = serde_json::to_string(&Welcome::reject(reason)).unwrap();
+                            self.send_message(MessageType::Welcome, welcome);
+                            self.flush();
+                            return false;
+                        }
+                    }
+                },
+                MessageType::LayoutData => {
+                    if let Some(layout_changed) = parse_json(&message.data.json_string) {
+                        let request_id = layout_changed.id();
+                        if !self.pending_requests.remove(&request_id) {
+                            println!("Discarding layout with id {}, it does not answer any request", request_id);
+                            self.send_event(ServerCommand::UnsolicitedLayout(request_id));
+                            continue;
+                        }
+                        let vn = layout_changed.to_nodes();
+
+                        self.send_event(ServerCommand::GotLayout(request_id, vn, message.data.json_string.clone()));
+                    }
+                },
+                MessageType::ResizeWindow => {
+                    //the app acknowledges a resize with the size its root ended up with
+                    match serde_json::from_str::<ScreenSize>(&message.data.json_string) {
+                        Ok(size) => {
+                            let (w, h) = size.size();
+                            self.send_event(ServerCommand::WindowResized(w, h));
+                        },
+                        Err(e) => {
+                            println!("Could not parse resize acknowledgement: {}", e);
+                        }
+                    }
+                },
+                MessageType::Log => {
+                    match serde_json::from_str::<ClientLog>(&message.data.json_string) {
+                        Ok(client_log) => {
+                            match self.session.as_ref().and_then(|s| s.log.as_ref()) {
+                                Some(log) => log.add(LogSource::Client, client_log.message),
+                                None => println!("App log: {}", client_log.message),
+                            }
+                        },
+                        Err(e) => {
+                            println!("Could not parse log message: {}", e);
+                        }
+                    }
+                },
+                MessageType::LayoutReady => {
+                    match serde_json::from_str::<LayoutReady>(&message.data.json_string) {
+                        Ok(ready) => self.send_event(ServerCommand::LayoutReady(ready.id)),
+                        Err(e) => {
+                            println!("Could not parse layout ready: {}", e);
+                        }
+                    }
+                },
+                //the read already noted that the app is alive
+                MessageType::Heartbeat => (),
+                MessageType::RequestLayoutData => (),
+                MessageType::Welcome => (),
+                MessageType::None => ()
+            }
         }
+        true
     }
 
-    fn decode_messages(&mut self) -> Vec<Message> {
-        let mut ret = Vec::new();
-        while let Some((message,bytes_read)) = Message::decode(self.buffer.as_slice()) {
-            self.buffer.drain(0..bytes_read as usize);
-            //println!("We just drained {} bytes", bytes_read);
-            ret.push(message);
+    /*Sends a heartbeat when it is time for one. Returns false if the app has been quiet for too long,
+    most likely it hangs or died without the socket being closed. Only apps that have the
+    Heartbeat capability are checked, older apps would never answer.*/
+    fn check_heartbeat(&mut self, now: Instant) -> bool {
+        let supported = self.client_info.as_ref().map_or(false, |info| info.has_capability(CAPABILITY_HEARTBEAT));
+        if !supported {
+            return true;
+        }
+        if now.duration_since(self.last_received) > Duration::from_secs(HEARTBEAT_TIMEOUT_SECS) {
+            println!("The app of session {:?} has not answered for {} seconds, disconnecting it",
+                     self.session_id(), HEARTBEAT_TIMEOUT_SECS);
+            return false;
         }
-        ret
+        if now.duration_since(self.last_heartbeat) >= Duration::from_millis(HEARTBEAT_INTERVAL_MS) {
+            let heartbeat = format!("{}", self.next_heartbeat);
+            self.next_heartbeat += 1;
+            self.last_heartbeat = now;
+            self.send_message(MessageType::Heartbeat, heartbeat);
+        }
+        true
     }
 
-    fn add_to_buffer(&mut self, buff: &mut [u8]) {
-        self.buffer.extend_from_slice(buff);
+    //returns false if the connection should be closed
+    fn handle_command(&mut self, command: ServerCommand) -> bool {
+        match command {
+            ServerCommand::RequestLayout(request_id) => {
+                println!("requesting layout {} from the app", request_id);
+                self.pending_requests.insert(request_id);
+                let data = format!("{}", request_id);
+                self.send_message(MessageType::RequestLayoutData, data);
+            },
+            ServerCommand::RequestLayoutReady(request_id) => {
+                println!("asking the app to tell when its layout is ready ({})", request_id);
+                self.send_message(MessageType::LayoutReady, format!("{}", request_id));
+            },
+            ServerCommand::ResizeWindow(w, h) => {
+                println!("asking the app to resize its root to {}x{}", w, h);
+                let size = ScreenSize { w: w as f32, h: h as f32 };
+                let data = serde_json::to_string(&size).unwrap();
+                self.send_message(MessageType::ResizeWindow, data);
+            },
+            ServerCommand::CloseConnection => {
+                println!("Got a close connection command");
+                return false;
+            },
+            ServerCommand::SetLog(log) => {
+                if let Some(ref mut session) = self.session {
+                    session.log = Some(log);
+                }
+            },
+            _ => ()
+        }
+        true
     }
 }
 
-//const SERVER: Token = Token(0);
+//all the state of the server thread
+struct ServerLoop {
+    poll: Poll,
+    listener: TcpListener,
+    connections: HashMap<Token, Connection>,
+    waiting_sessions: Vec<WaitingSession>,
+    next_token: usize,
+    read_buffer: Vec<u8>,
+}
+
+impl ServerLoop {
+    fn accept_connections(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, addr)) => {
+                    let token = Token(self.next_token);
+                    self.next_token += 1;
+                    println!("new client from {}!", addr);
+                    self.poll.register(&stream, token, Ready::readable(), PollOpt::edge()).unwrap();
+                    self.connections.insert(token, Connection::new(stream));
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    println!("Could not accept connection: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn handle_command(&mut self, session_id: SessionId, command: ServerCommand) {
+        if let ServerCommand::ExpectClient(events) = command {
+            self.waiting_sessions.push(WaitingSession { id: session_id, events: events, log: None });
+            return;
+        }
+
+        let token = self.connections.iter()
+            .find(|&(_, c)| c.session_id() == Some(session_id))
+            .map(|(token, _)| *token);
+
+        match token {
+            Some(token) => {
+                let keep_connection = self.connections.get_mut(&token).unwrap().handle_command(command);
+                self.update_connection(token, keep_connection);
+            },
+            None => {
+                //the app of this session has not connected yet, or is gone
+                match command {
+                    ServerCommand::CloseConnection => {
+                        self.waiting_sessions.retain(|s| s.id != session_id);
+                    },
+                    ServerCommand::SetLog(log) => {
+                        if let Some(session) = self.waiting_sessions.iter_mut().find(|s| s.id == session_id) {
+                            session.log = Some(log);
+                        }
+                    },
+                    command => {
+                        println!("Discarding {:?}, the app of session {} is not connected", command, session_id.0);
+                        if let Some(session) = self.waiting_sessions.iter().find(|s| s.id == session_id) {
+                            session.events.send(ServerCommand::ClientDisconnected);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_client_event(&mut self, token: Token, readiness: Ready) {
+        let keep_connection = match self.connections.get_mut(&token) {
+            Some(connection) => {
+                if readiness.is_readable() {
+                    let open = connection.read(&mut self.read_buffer);
+                    //messages that arrived before the close are still handled
+                    connection.handle_messages(&mut self.waiting_sessions) && open
+                } else {
+                    true
+                }
+            },
+            None => return,
+        };
+        self.update_connection(token, keep_connection);
+    }
+
+    fn check_heartbeats(&mut self) {
+        let now = Instant::now();
+        let tokens: Vec<Token> = self.connections.keys().cloned().collect();
+        for token in tokens {
+            let keep_connection = self.connections.get_mut(&token).unwrap().check_heartbeat(now);
+            self.update_connection(token, keep_connection);
+        }
+    }
+
+    //sends what is queued for the connection, or closes it
+    fn update_connection(&mut self, token: Token, keep_connection: bool) {
+        let keep_connection = keep_connection && self.connections.get_mut(&token).unwrap().flush();
+        if keep_connection {
+            let connection = self.connections.get(&token).unwrap();
+            self.poll.reregister(&connection.stream, token, connection.interest(), PollOpt::edge()).unwrap();
+        } else {
+            let connection = self.connections.remove(&token).unwrap();
+            self.poll.deregister(&connection.stream).unwrap();
+            connection.send_event(ServerCommand::ClientDisconnected);
+        }
+    }
+}
 
+/*Hands out sessions, one for each app under test. The server itself can be cloned,
+so apps can be launched and waited for from several threads.*/
+#[derive(Clone)]
 pub struct Server {
-    sender: Sender<ServerCommand>,
+    sender: CommandSender,
+    next_session_id: Arc<AtomicUsize>,
+    //how long to wait for the app to connect and finish the handshake
+    connect_timeout: Duration,
+    //how long to wait for the app to answer a resize or a layout request
+    response_timeout: Duration,
+    //how long to wait for the app's layout to settle before capturing it anyway
+    layout_ready_timeout: Duration,
+}
+
+/*The connection to one app. Talks to the server thread through its own channel,
+so any number of sessions can wait for their apps at the same time.*/
+pub struct Session {
+    id: SessionId,
+    sender: CommandSender,
     receiver: Receiver<ServerCommand>,
+    //what was negotiated with the app in the handshake
+    client_info: Option<ClientInfo>,
+    connect_timeout: Duration,
+    response_timeout: Duration,
+    layout_ready_timeout: Duration,
+    //ids are unique for the lifetime of the session, so a late answer can never match a newer request
+    next_request_id: Cell<i32>,
+    mismatches: RefCell<Vec<LayoutMismatch>>,
 }
 
 #[derive(Debug)]
 enum ServerCommand {
-    AcceptConnection,
-    ClientConnected,
+    ExpectClient(Sender<ServerCommand>),
+    ClientConnected(ClientInfo),
     CloseConnection,
     ClientDisconnected,
-    RequestLayout,
-    GotLayout(Nodes),
+    RequestLayout(i32),
+    GotLayout(i32, Nodes, String),
+    UnsolicitedLayout(i32),
+    ResizeWindow(i32,i32),
+    WindowResized(i32,i32),
+    RequestLayoutReady(i32),
+    LayoutReady(i32),
+    SetLog(AppLog),
 }
 
 impl Server {
 
     pub fn start_new(addr: &str) -> Server {
-        let addr = addr.to_string();
+        let addr: SocketAddr = addr.parse().unwrap();
 
-        let (from_server_tx, from_server_rx) = channel::<ServerCommand>();
-        let (to_server_tx, to_server_rx) = channel::<ServerCommand>();
+        let (to_server_tx, to_server_rx) = channel::<(SessionId, ServerCommand)>();
+        let (registration, set_readiness) = Registration::new2();
+        let thread_set_readiness = set_readiness.clone();
 
         //need to end the thread when we are done
         let server_thread_handle = spawn(move ||{
-            let mut server_buffer = ServerBuffer::new();
-            let listener = TcpListener::bind(addr).unwrap();
-
-            for stream in listener.incoming() {
+            let poll = Poll::new().unwrap();
+            let listener = TcpListener::bind(&addr).unwrap();
+            poll.register(&listener, LISTENER, Ready::readable(), PollOpt::edge()).unwrap();
+            poll.register(&registration, COMMANDS, Ready::readable(), PollOpt::edge()).unwrap();
 
+            let mut server_loop = ServerLoop {
+                poll: poll,
+                listener: listener,
+                connections: HashMap::new(),
+                waiting_sessions: Vec::new(),
+                next_token: FIRST_CLIENT,
+                read_buffer: vec![0; READ_BUFFER_SIZE],
+            };
+            let mut events = Events::with_capacity(64);
 
+            loop {
+                //wakes up at least once per heartbeat interval, even when nothing happens
+                server_loop.poll.poll(&mut events, Some(Duration::from_millis(HEARTBEAT_INTERVAL_MS))).unwrap();
 
-                match stream {
-                    Ok(mut stream) => {
+                for event in events.iter() {
+                    match event.token() {
+                        LISTENER => server_loop.accept_connections(),
+                        COMMANDS => {
+                            //cleared before draining, so a command sent while we drain wakes us up again
+                            thread_set_readiness.set_readiness(Ready::empty());
+                            while let Ok((session_id, command)) = to_server_rx.try_recv() {
+                                server_loop.handle_command(session_id, command);
+                            }
+                        },
+                        token => server_loop.handle_client_event(token, event.readiness()),
+                    }
+                }
+                server_loop.check_heartbeats();
+            }
+        });
 
-                        println!("new client!");
-                        println!("WE GOT A NEW CONNECTION!");
+        Server {
+            sender: CommandSender {
+                sender: to_server_tx,
+                set_readiness: set_readiness,
+            },
+            next_session_id: Arc::new(AtomicUsize::new(0)),
+            connect_timeout: Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS),
+            response_timeout: Duration::from_secs(DEFAULT_RESPONSE_TIMEOUT_SECS),
+            layout_ready_timeout: Duration::from_secs(DEFAULT_LAYOUT_READY_TIMEOUT_SECS),
+        }
+    }
 
-                        from_server_tx.send(ServerCommand::ClientConnected);
+    pub fn set_connect_timeout(&mut self, connect_timeout: Duration) {
+        self.connect_timeout = connect_timeout;
+    }
 
-                        //TODO: decode and encode message now!!
+    pub fn set_response_timeout(&mut self, response_timeout: Duration) {
+        self.response_timeout = response_timeout;
+    }
 
-                        let mut id_counter = 0;
+    pub fn set_layout_ready_timeout(&mut self, layout_ready_timeout: Duration) {
+        self.layout_ready_timeout = layout_ready_timeout;
+    }
 
-                        stream.set_nonblocking(true).expect("set_nonblocking call failed");
+    /*Creates a session and starts waiting for its app. The app should be started with the
+    session's key in the SESSION_ENV_VAR environment variable.*/
+    pub fn new_session(&self) -> Session {
+        let id = SessionId(self.next_session_id.fetch_add(1, Ordering::SeqCst));
+        let (events_tx, events_rx) = channel::<ServerCommand>();
+        self.sender.send(id, ServerCommand::ExpectClient(events_tx));
 
-                        'outer: loop {
+        Session {
+            id: id,
+            sender: self.sender.clone(),
+            receiver: events_rx,
+            client_info: None,
+            connect_timeout: self.connect_timeout,
+            response_timeout: self.response_timeout,
+            layout_ready_timeout: self.layout_ready_timeout,
+            next_request_id: Cell::new(0),
+            mismatches: RefCell::new(Vec::new()),
+        }
+    }
+}
 
+impl Session {
+    pub fn id(&self) -> SessionId {
+        self.id
+    }
 
-                            let mut bytes = [0;2048];
-                            let mut br: i32 = -1;
-                            if let Ok(bytes_read) = stream.read(&mut bytes) {
-                                br = bytes_read as i32;
+    //what the app passes back in its Hello, see protocol::SESSION_ENV_VAR
+    pub fn key(&self) -> String {
+        format!("{}", self.id.0)
+    }
 
-                                server_buffer.add_to_buffer(&mut bytes[0..bytes_read]);
-                            }
+    fn record_mismatch(&self, mismatch: LayoutMismatch) {
+        println!("Session {}: discarding {}", self.id.0, mismatch);
+        self.mismatches.borrow_mut().push(mismatch);
+    }
 
-                            //println!("Reading {}", br);
-
-                            if server_buffer.buffer.len() > 0 {
-
-                                let decoded_messages = server_buffer.decode_messages();
-                                for message in decoded_messages {
-                                    match message.message_type {
-                                        MessageType::LayoutData => {
-                                            let layout_changed = parse_json(&message.data.json_string);
-                                            let screen_size = layout_changed.screen_size;
-                                            //println!("Got sent screen size as well now {:?}", screen_size);
-                                            let nodes = layout_changed.nodes;
-                                            let validator_nodes = nodes.into_validator_node();
-                                            let vn = Nodes::new(validator_nodes, (screen_size.w as i32, screen_size.h as i32));
-
-                                            from_server_tx.send(ServerCommand::GotLayout(vn));
-                                        },
-                                        MessageType::RequestLayoutData => (),
-                                        MessageType::None => ()
-                                    }
-                                }
-                            }
+    //the layouts that were discarded since the last call, for the report
+    pub fn take_mismatches(&self) -> Vec<LayoutMismatch> {
+        mem::replace(&mut *self.mismatches.borrow_mut(), Vec::new())
+    }
 
+    fn recv_before(&self, deadline: Instant, operation: &'static str) -> Result<ServerCommand, ServerError> {
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(ServerError::Timeout(operation));
+            }
+            match self.receiver.recv_timeout(deadline - now) {
+                Ok(ServerCommand::UnsolicitedLayout(id)) => self.record_mismatch(LayoutMismatch::Unsolicited(id)),
+                Ok(command) => return Ok(command),
+                Err(RecvTimeoutError::Timeout) => return Err(ServerError::Timeout(operation)),
+                Err(RecvTimeoutError::Disconnected) => return Err(ServerError::ServerStopped),
+            }
+        }
+    }
 
-                            while let Ok(command) = to_server_rx.try_recv() {
-                                match command {
-                                    ServerCommand::RequestLayout => {
-                                        println!("requesting layout from the app");
-                                        let data = format!("{}", id_counter);
-                                        id_counter += 1;
-                                        let message = Message {
-                                            message_type: MessageType::RequestLayoutData,
-                                            length: data.len() as i32,
-                                            data: MessageData {
-                                                json_string: data
-                                            },
-                                        };
-                                        stream.write(message.as_bytes().as_slice());
-                                    },
-                                    ServerCommand::CloseConnection => {
-                                        println!("Got a close connection command");
-                                        break 'outer;
-                                    },
-                                    _ => ()
-                                }
-                            }
+    pub fn wait_for_client(&mut self) -> Result<(), ServerError> {
+        self.wait_for_client_while(|| true)
+    }
 
+    /*Like wait_for_client, but asks app_running every now and then and gives up when it returns
+    false, so an app that dies on startup does not keep us waiting for the whole connect timeout.*/
+    pub fn wait_for_client_while<F>(&mut self, mut app_running: F) -> Result<(), ServerError>
+        where F: FnMut() -> bool {
+        println!("Session {}: waiting for client", self.id.0);
 
-                            sleep_ms(50);
-                        }
+        let deadline = Instant::now() + self.connect_timeout;
+        loop {
+            let check_at = min(deadline, Instant::now() + Duration::from_millis(APP_RUNNING_CHECK_MS));
+            match self.recv_before(check_at, "the app to connect") {
+                Ok(ServerCommand::ClientConnected(client_info)) => {
+                    self.client_info = Some(client_info);
+                    return Ok(());
+                },
+                Ok(_) => (),
+                Err(ServerError::Timeout(operation)) => {
+                    if Instant::now() >= deadline {
+                        return Err(ServerError::Timeout(operation));
                     }
-                    Err(e) => { /* connection failed */ }
-                }
-
-                from_server_tx.send(ServerCommand::ClientDisconnected);
+                    if !app_running() {
+                        println!("Session {}: the app exited before it connected", self.id.0);
+                        return Err(ServerError::ClientDisconnected);
+                    }
+                },
+                Err(e) => return Err(e),
             }
-        });
-
-        Server {
-            sender: to_server_tx,
-            receiver: from_server_rx,
         }
     }
 
+    //the Log messages of the app are added to the given log from now on
+    pub fn set_log(&self, log: AppLog) {
+        self.sender.send(self.id, ServerCommand::SetLog(log));
+    }
+
+    pub fn client_info(&self) -> Option<&ClientInfo> {
+        self.client_info.as_ref()
+    }
+
+    //asks the app to resize its root (size in points), returns the size the app reports back
+    pub fn resize_window(&self, size: (i32,i32)) -> Result<(i32,i32), ServerError> {
+        println!("Session {}: requesting resize to {:?}", self.id.0, size);
 
-    pub fn wait_for_client(&mut self) {
-        println!("waiting for client");
-        while let Ok(cc) = self.receiver.recv() {
-            match cc {
-                ServerCommand::ClientConnected => { break; }
-                _ => ()
+        self.sender.send(self.id, ServerCommand::ResizeWindow(size.0, size.1));
+
+        let deadline = Instant::now() + self.response_timeout;
+        loop {
+            match self.recv_before(deadline, "the app to resize")? {
+                ServerCommand::WindowResized(w, h) => {
+                    println!("Session {}: app resized to {}x{}", self.id.0, w, h);
+                    return Ok((w, h))
+                }
+                ServerCommand::ClientDisconnected => {
+                    return Err(ServerError::ClientDisconnected)
+                }
+                command => {
+                    println!("got other command: {:?}", command);
+                }
             }
         }
     }
 
-    pub fn close_current_connection(&mut self) {
-        println!("Trying to close current connection");
-        self.sender.send(ServerCommand::CloseConnection);
+    fn next_request_id(&self) -> i32 {
+        let request_id = self.next_request_id.get();
+        self.next_request_id.set(request_id + 1);
+        request_id
     }
 
-    pub fn request_layout_data(&self, id: i32) -> Option<(Nodes, i32)> {
-        println!("Writing request");
+    /*Waits until the app reports that its layout has stopped changing. Returns false if it
+    did not settle in time, the caller can still capture it. Apps without the LayoutReady
+    capability can't tell us, so for them we just wait the fallback time and hope.*/
+    pub fn wait_for_layout_ready(&self, fallback: Duration) -> Result<bool, ServerError> {
+        let supported = self.client_info.as_ref().map_or(false, |info| info.has_capability(CAPABILITY_LAYOUT_READY));
+        if !supported {
+            sleep(fallback);
+            return Ok(true);
+        }
 
-        self.sender.send(ServerCommand::RequestLayout);
+        let request_id = self.next_request_id();
+        self.sender.send(self.id, ServerCommand::RequestLayoutReady(request_id));
 
-        for command in self.receiver.recv() {
+        let deadline = Instant::now() + self.layout_ready_timeout;
+        loop {
+            let command = match self.recv_before(deadline, "the layout to settle") {
+                Ok(command) => command,
+                Err(ServerError::Timeout(_)) => {
+                    println!("Session {}: the layout did not settle in time", self.id.0);
+                    return Ok(false);
+                },
+                Err(e) => return Err(e),
+            };
             match command {
-                ServerCommand::GotLayout(nodes) => {
+                ServerCommand::LayoutReady(got_id) if got_id == request_id => {
+                    return Ok(true)
+                }
+                ServerCommand::ClientDisconnected => {
+                    return Err(ServerError::ClientDisconnected)
+                }
+                command => {
+                    println!("got other command: {:?}", command);
+                }
+            }
+        }
+    }
+
+    //returns the nodes together with the raw json payload they were parsed from
+    pub fn request_layout_data(&self, id: i32) -> Result<(Nodes, i32, String), ServerError> {
+        println!("Session {}: writing request", self.id.0);
+
+        let request_id = self.next_request_id();
+        self.sender.send(self.id, ServerCommand::RequestLayout(request_id));
+
+        let deadline = Instant::now() + self.response_timeout;
+        loop {
+            match self.recv_before(deadline, "layout data")? {
+                ServerCommand::GotLayout(got_id, nodes, json_string) => {
+                    if got_id != request_id {
+                        self.record_mismatch(LayoutMismatch::Stale { expected: request_id, got: got_id });
+                        continue;
+                    }
                     println!("Got nodes in return");
-                    return Some((nodes, id))
+                    return Ok((nodes, id, json_string))
                 }
-                _ => {
+                ServerCommand::ClientDisconnected => {
+                    return Err(ServerError::ClientDisconnected)
+                }
+                command => {
                     println!("got other command: {:?}", command);
                 }
             }
         }
-        panic!("We should have gotten something here!");
     }
 
+    //closes the connection to the app, or stops waiting for it if it never connected
+    pub fn close(self) {
+        //dropping the session is what closes it
+    }
+}
 
+impl Drop for Session {
+    fn drop(&mut self) {
+        println!("Closing session {}", self.id.0);
+        self.sender.send(self.id, ServerCommand::CloseConnection);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
+    fn waiting_sessions(ids: &[usize]) -> Vec<WaitingSession> {
+        ids.iter().map(|&id| {
+            let (events, _) = channel();
+            WaitingSession { id: SessionId(id), events: events, log: None }
+        }).collect()
+    }
 
+    #[test]
+    fn claim_session_by_key() {
+        let mut waiting = waiting_sessions(&[1, 2, 3]);
+        let session = Connection::claim_session(&Hello::new(Some("2".to_string())), &mut waiting).unwrap();
+        assert_eq!(session.id, SessionId(2));
+        assert_eq!(waiting.len(), 2);
+
+        assert!(Connection::claim_session(&Hello::new(Some("2".to_string())), &mut waiting).is_err());
+        assert!(Connection::claim_session(&Hello::new(Some("not a key".to_string())), &mut waiting).is_err());
+        assert_eq!(waiting.len(), 2);
+    }
+
+    #[test]
+    fn claim_session_without_key() {
+        //with more than one waiting the client could be any of the apps
+        let mut waiting = waiting_sessions(&[1, 2]);
+        assert!(Connection::claim_session(&Hello::new(None), &mut waiting).is_err());
+        assert_eq!(waiting.len(), 2);
+
+        let mut waiting = waiting_sessions(&[1]);
+        let session = Connection::claim_session(&Hello::new(None), &mut waiting).unwrap();
+        assert_eq!(session.id, SessionId(1));
+
+        assert!(Connection::claim_session(&Hello::new(None), &mut waiting).is_err());
+    }
 }
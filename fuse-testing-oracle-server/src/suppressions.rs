@@ -0,0 +1,214 @@
+use layout_validator::Node;
+use layout_validator::validity_rules::{LayoutViolation,LayoutViolations};
+use test_sets::*;
+use std::fmt;
+use normalize_path;
+
+/*A violation the developers know about and accept, like a badge drawn over an avatar.
+Every field that is set has to match. File, Line and Element are matched against the nodes
+of the violation, and a single node has to match all of them.*/
+#[derive(Deserialize,Debug,Clone)]
+pub struct Suppression {
+    //the rule name, like Overlap or AlignmentLost
+    #[serde(rename = "Kind", default)] pub kind: Option<String>,
+    //the ux file, matched against the end of the path the app reports
+    #[serde(rename = "File", default)] pub file: Option<String>,
+    #[serde(rename = "Line", default)] pub line: Option<i32>,
+    //the name of the element, as in the ux:Name
+    #[serde(rename = "Element", default)] pub element: Option<String>,
+    //why this is fine, shown in the report
+    #[serde(rename = "Reason", default)] pub reason: Option<String>,
+}
+
+impl Suppression {
+    //a suppression without anything to match on would suppress everything
+    pub fn is_empty(&self) -> bool {
+        self.kind.is_none() && self.file.is_none() && self.line.is_none() && self.element.is_none()
+    }
+
+    fn matches_node(&self, node: &Node) -> bool {
+        if let Some(ref file) = self.file {
+            let node_file = normalize_path(&node.node_data.file).to_lowercase();
+            let file = normalize_path(file).to_lowercase();
+            if node_file != file && !node_file.ends_with(&format!("/{}", file)) {
+                return false;
+            }
+        }
+        if let Some(line) = self.line {
+            if node.node_data.line != line {
+                return false;
+            }
+        }
+        if let Some(ref element) = self.element {
+            if node.node_data.name != *element {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn matches(&self, violation: &LayoutViolation, test_sets: &TestSets) -> bool {
+        if let Some(ref kind) = self.kind {
+            if violation.kind() != kind {
+                return false;
+            }
+        }
+
+        if self.file.is_none() && self.line.is_none() && self.element.is_none() {
+            return !self.is_empty();
+        }
+
+        let violation_test_sets = violation.test_sets();
+        violation.nodes().iter().any(|node| {
+            //the same node can be looked up in any of the test sets the violation is in
+            let node = violation_test_sets.iter()
+                .filter_map(|id| test_sets.sets.get(id))
+                .filter_map(|test_set| test_set.nodes.get_from_id(*node))
+                .next();
+            match node {
+                Some(node) => self.matches_node(node),
+                None => false,
+            }
+        })
+    }
+}
+
+impl fmt::Display for Suppression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(ref kind) = self.kind { parts.push(format!("kind {}", kind)); }
+        if let Some(ref file) = self.file { parts.push(format!("file {}", file)); }
+        if let Some(line) = self.line { parts.push(format!("line {}", line)); }
+        if let Some(ref element) = self.element { parts.push(format!("element {}", element)); }
+        match self.reason {
+            Some(ref reason) => write!(f, "{} ({})", parts.join(", "), reason),
+            None => write!(f, "{}", parts.join(", ")),
+        }
+    }
+}
+
+/*Moves the violations that match a suppression out of the reported ones and into
+violations.suppressed, like remove_baseline_violations does for the baseline. The first
+suppression that matches is kept with the violation, so the reports can say why.*/
+pub fn remove_suppressed_violations(violations: &mut LayoutViolations, test_sets: &TestSets, suppressions: &Vec<Suppression>) {
+    if suppressions.iter().any(|s| s.is_empty()) {
+        println!("Ignoring suppressions in the test script that have no Kind, File, Line or Element");
+    }
+    if suppressions.len() == 0 {
+        return;
+    }
+
+    let mut suppressed = Vec::new();
+    {
+        let mut remove_from = |list: &mut Vec<LayoutViolation>| {
+            let mut rest = Vec::new();
+            for v in list.drain(..) {
+                match suppressions.iter().find(|s| s.matches(&v, test_sets)) {
+                    Some(suppression) => suppressed.push((v, suppression.clone())),
+                    None => rest.push(v),
+                }
+            }
+            *list = rest;
+        };
+        remove_from(&mut violations.overflows);
+        remove_from(&mut violations.overlaps);
+        remove_from(&mut violations.alignment_changes);
+        remove_from(&mut violations.unstable_layouts);
+        remove_from(&mut violations.custom);
+    }
+
+    println!("Suppressed {} violations", suppressed.len());
+    violations.suppressed.extend(suppressed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use server::{JSONNode,LayoutChanged,ScreenSize};
+    use layout_validator::validate_layout;
+    use text_report::generate_violations_report;
+
+    fn suppression(kind: Option<&str>, file: Option<&str>, line: Option<i32>, element: Option<&str>) -> Suppression {
+        Suppression {
+            kind: kind.map(String::from),
+            file: file.map(String::from),
+            line: line,
+            element: element.map(String::from),
+            reason: None,
+        }
+    }
+
+    //an avatar with a badge drawn over its corner
+    fn avatar_and_badge() -> TestSets {
+        let root = JSONNode::new("Root", "C:\\work\\App\\Pages\\Main.ux", 1, (0, 0, 300, 600), vec![
+            JSONNode::new("Avatar", "C:\\work\\App\\Pages\\Main.ux", 2, (0, 0, 100, 100), vec![]),
+            JSONNode::new("Badge", "C:\\work\\App\\Pages\\Main.ux", 12, (60, 60, 80, 80), vec![]),
+        ]);
+        let nodes = LayoutChanged::new(0, ScreenSize::new(300.0, 600.0), root).to_nodes();
+        generate_test_sets(vec![(TestSetId(0), nodes, (300, 600))], HashMap::new(), 0.0)
+    }
+
+    #[test]
+    fn matches_node_on_file_line_and_element() {
+        let test_sets = avatar_and_badge();
+        let badge = test_sets.sets[&TestSetId(0)].nodes.get_from_id(2).unwrap();
+        assert_eq!(badge.node_data.name, "Badge");
+
+        //files match at the end of the path, on whole path segments, in any case and with either slash
+        assert!(suppression(None, Some("Pages/Main.ux"), None, None).matches_node(badge));
+        assert!(suppression(None, Some("pages\\main.ux"), None, None).matches_node(badge));
+        assert!(suppression(None, Some("C:/work/App/Pages/Main.ux"), None, None).matches_node(badge));
+        assert!(!suppression(None, Some("ain.ux"), None, None).matches_node(badge));
+        assert!(!suppression(None, Some("Other/Main.ux"), None, None).matches_node(badge));
+
+        assert!(suppression(None, None, Some(12), None).matches_node(badge));
+        assert!(!suppression(None, None, Some(13), None).matches_node(badge));
+        assert!(suppression(None, None, None, Some("Badge")).matches_node(badge));
+        assert!(!suppression(None, None, None, Some("badge")).matches_node(badge));
+
+        //everything that is set has to match
+        assert!(suppression(None, Some("Main.ux"), Some(12), Some("Badge")).matches_node(badge));
+        assert!(!suppression(None, Some("Main.ux"), Some(2), Some("Badge")).matches_node(badge));
+    }
+
+    #[test]
+    fn suppressed_violations_keep_their_suppression() {
+        let test_sets = avatar_and_badge();
+        let mut violations = validate_layout(&test_sets);
+        let overlaps = violations.overlaps.len();
+        assert!(overlaps > 0);
+
+        let mut badge = suppression(Some("Overlap"), None, None, Some("Badge"));
+        badge.reason = Some("the badge sits on the avatar".to_string());
+        let suppressions = vec![
+            //of another kind, and without anything to match on, so neither suppresses anything
+            suppression(Some("Overflow"), None, None, Some("Badge")),
+            suppression(None, None, None, None),
+            badge,
+            suppression(Some("Overlap"), None, None, None),
+        ];
+        remove_suppressed_violations(&mut violations, &test_sets, &suppressions);
+        assert_eq!(violations.overlaps.len(), 0);
+        assert_eq!(violations.suppressed.len(), overlaps);
+
+        //the first suppression that matches is the one shown
+        let shown = "kind Overlap, element Badge (the badge sits on the avatar)";
+        for &(ref violation, ref suppression) in &violations.suppressed {
+            assert_eq!(violation.kind(), "Overlap");
+            assert_eq!(format!("{}", suppression), shown);
+        }
+        let report = generate_violations_report(&test_sets, &violations);
+        assert!(report.contains(&format!("suppressed by {}", shown)), "{}", report);
+    }
+
+    #[test]
+    fn no_suppressions_leave_the_violations() {
+        let test_sets = avatar_and_badge();
+        let mut violations = validate_layout(&test_sets);
+        let overlaps = violations.overlaps.len();
+        remove_suppressed_violations(&mut violations, &test_sets, &vec![suppression(None, None, None, None)]);
+        assert_eq!(violations.overlaps.len(), overlaps);
+        assert_eq!(violations.suppressed.len(), 0);
+    }
+}
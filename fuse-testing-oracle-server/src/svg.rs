@@ -0,0 +1,202 @@
+use std::fmt::Write;
+use std::io::Write as IoWrite;
+use std::fs::File;
+use sdl2::rect::Rect;
+use test_sets::*;
+use random_color::RandomColor;
+use layout_validator::{Orientation,TabStop};
+use layout_validator::validity_rules::{LayoutViolation,LayoutViolations};
+use drawing::{violation_image_names,screen_size_label,alignment_lost_test_sets};
+use junit_report::escape_xml;
+
+pub fn kind_color(violation: &LayoutViolation) -> &'static str {
+    match violation {
+        &LayoutViolation::Overlap { .. } => "#e00000",
+        &LayoutViolation::Overflow { .. } => "#e08000",
+        &LayoutViolation::AlignmentLost { .. } => "#0060e0",
+        &LayoutViolation::UnstableLayout { .. } => "#8000c0",
+        &LayoutViolation::Custom(_) => "#880088",
+    }
+}
+
+//attributes are written as is, like class="node" fill="#fff"
+pub fn svg_rect(out: &mut String, rect: &Rect, attributes: &str) {
+    write!(out, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" {}/>",
+           rect.x(), rect.y(), rect.width(), rect.height(), attributes);
+}
+
+//a line across the whole screen
+pub fn svg_tab_stop(out: &mut String, test_set: &TestSet, tab_stop: &TabStop, attributes: &str) {
+    let (w, h) = test_set.screen_size;
+    let (x1, y1, x2, y2) = match tab_stop.orientation {
+        Orientation::Horizontal => (0, tab_stop.pos, w, tab_stop.pos),
+        Orientation::Vertical => (tab_stop.pos, 0, tab_stop.pos, h),
+    };
+    write!(out, "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" {}/>", x1, y1, x2, y2, attributes);
+}
+
+pub fn svg_tab_stops(out: &mut String, test_set: &TestSet) {
+    write!(out, "<g class=\"tab-stops\">");
+    for tab_stop in test_set.tab_stops.sorted() {
+        svg_tab_stop(out, test_set, &tab_stop, "stroke=\"#000\" stroke-width=\"1\" stroke-opacity=\"0.4\"");
+    }
+    write!(out, "</g>");
+}
+
+/*The nodes as rects, biggest first so the small ones end up on top, like DrawContext::draw_nodes.
+They get the same colors as in the PNGs, and a tooltip with their id, name and line.*/
+pub fn svg_nodes(out: &mut String, test_set: &TestSet) {
+    let random_color = RandomColor::new();
+    let mut nodes = test_set.nodes.sorted_by_line();
+    nodes.sort_by_key(|n| -(((n.node_data.render_width.pow(2) + n.node_data.render_height.pow(2)) as f32).sqrt() as i32));
+
+    write!(out, "<g class=\"nodes\">");
+    for node in &nodes {
+        let c = random_color.get_a_color(node.id as usize);
+        write!(out, "<rect class=\"node\" data-node=\"{}\" x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"rgb({},{},{})\"><title>{}</title></rect>",
+               node.id,
+               node.node_data.render_position_x, node.node_data.render_position_y,
+               node.node_data.render_width, node.node_data.render_height,
+               c.0, c.1, c.2,
+               escape_xml(&format!("{} - {} @ {}:{}", node.id, node.node_data.name, node.node_data.file, node.node_data.line)));
+    }
+    write!(out, "</g>");
+}
+
+//the nodes of a violation and its rects or tab stops, drawn over the nodes of the test set
+pub fn svg_violation(out: &mut String, test_set: &TestSet, violation: &LayoutViolation) {
+    let color = kind_color(violation);
+    let random_color = RandomColor::new();
+    let fill = format!("fill=\"{}\" fill-opacity=\"0.5\" stroke=\"{}\"", color, color);
+
+    for node in violation.nodes() {
+        if test_set.nodes.get_from_id(node).is_some() {
+            let c = random_color.get_a_color(node as usize);
+            svg_rect(out, &test_set.nodes.rect_of(&node),
+                     &format!("class=\"highlight\" fill=\"rgb({},{},{})\" stroke=\"{}\" stroke-width=\"3\"", c.0, c.1, c.2, color));
+        }
+    }
+
+    match violation {
+        &LayoutViolation::Overlap { ref intersection_rect, .. } => {
+            svg_rect(out, intersection_rect, &fill);
+        },
+        &LayoutViolation::Overflow { ref overflow_rect, .. } => {
+            for rect in overflow_rect.rects() {
+                svg_rect(out, &rect, &fill);
+            }
+        },
+        &LayoutViolation::AlignmentLost { a, b, .. } => {
+            for node_side in &[a, b] {
+                if let Some(tab_stop) = test_set.tab_stops.find_tab_stop_for_node_side(node_side) {
+                    svg_tab_stop(out, test_set, &tab_stop,
+                                 &format!("stroke=\"{}\" stroke-width=\"3\" stroke-dasharray=\"12,6\"", color));
+                }
+            }
+        },
+        &LayoutViolation::UnstableLayout { ref rects, .. } => {
+            for rect in rects {
+                svg_rect(out, rect, &format!("fill=\"none\" stroke=\"{}\" stroke-width=\"2\" stroke-dasharray=\"6,4\"", color));
+            }
+        },
+        &LayoutViolation::Custom(ref custom) => {
+            for rect in &custom.rects {
+                svg_rect(out, rect, &fill);
+            }
+        },
+    }
+}
+
+//the nodes of a test set with the violations highlighted over a faded copy of them, like in the PNGs
+fn svg_test_set(out: &mut String, test_set: &TestSet, violations: &Vec<&LayoutViolation>, with_tab_stops: bool) {
+    let (w, h) = test_set.screen_size;
+    write!(out, "<rect x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" fill=\"#fff\"/>", w, h);
+    svg_nodes(out, test_set);
+    if violations.len() > 0 {
+        write!(out, "<rect x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" fill=\"#fff\" fill-opacity=\"0.87\"/>", w, h);
+    }
+    if with_tab_stops {
+        svg_tab_stops(out, test_set);
+    }
+    for violation in violations {
+        write!(out, "<g class=\"violation\" data-kind=\"{}\"><title>{}</title>",
+               escape_xml(violation.kind()), escape_xml(&violation.describe(test_set)));
+        svg_violation(out, test_set, violation);
+        write!(out, "</g>");
+    }
+}
+
+//a test set as an svg file, each violation in a group with its description as the tooltip
+pub fn generate_svg(test_set: &TestSet, violations: &Vec<&LayoutViolation>, with_tab_stops: bool) -> String {
+    let (w, h) = test_set.screen_size;
+    let mut out = String::new();
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+    write!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">", w, h, w, h);
+    svg_test_set(&mut out, test_set, violations, with_tab_stops);
+    writeln!(out, "</svg>");
+    out
+}
+
+//an alignment change in the test sets of alignment_lost_test_sets, side by side, each with a label
+pub fn generate_alignment_lost_svg(test_sets: &TestSets, violation: &LayoutViolation, screen_size_names: &Vec<(TestSetId, String)>, with_tab_stops: bool) -> String {
+    let panels = alignment_lost_test_sets(test_sets, violation);
+    let width = panels.iter().map(|&(ts, _)| ts.screen_size.0).sum::<i32>();
+    let height = panels.iter().map(|&(ts, _)| ts.screen_size.1).max().unwrap_or(0);
+
+    let mut out = String::new();
+    writeln!(out, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>");
+    write!(out, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">", width, height, width, height);
+    let mut x = 0;
+    for &(test_set, shows) in &panels {
+        let (w, h) = test_set.screen_size;
+        write!(out, "<svg x=\"{}\" y=\"0\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\">", x, w, h, w, h);
+        svg_test_set(&mut out, test_set, &vec![violation], with_tab_stops);
+        write!(out, "<text x=\"4\" y=\"18\" font-family=\"sans-serif\" font-size=\"14\">{}</text>",
+               escape_xml(&format!("{} - {}", screen_size_label(test_set, screen_size_names), shows)));
+        write!(out, "</svg>");
+        if x > 0 {
+            write!(out, "<line x1=\"{}\" y1=\"0\" x2=\"{}\" y2=\"{}\" stroke=\"#000\"/>", x, x, height);
+        }
+        x += w;
+    }
+    writeln!(out, "</svg>");
+    out
+}
+
+fn save_svg(path: &str, svg: &str) {
+    println!("savepath: {}", path);
+    if let Err(e) = File::create(path).and_then(|mut f| f.write_all(svg.as_bytes())) {
+        println!("Could not save {}: {}", path, e);
+    }
+}
+
+//every screen size as output/{folder}/size{w}x{h}.svg, next to the PNGs of run_all_tests
+pub fn save_svg_test_sets(test_sets: &TestSets, folder: &str, with_tab_stops: bool) {
+    for (_, test_set) in &test_sets.sets {
+        let ss = test_set.screen_size;
+        let svg = generate_svg(test_set, &Vec::new(), with_tab_stops);
+        save_svg(&format!("output/{}/size{}x{}.svg", folder, ss.0, ss.1), &svg);
+    }
+}
+
+/*The svg versions of the images the save_*_violations functions of DrawContext write, named by
+violation_image_names too, and drawn in the same test sets.*/
+pub fn save_svg_violations(test_sets: &TestSets, violations: &LayoutViolations, screen_size_names: &Vec<(TestSetId, String)>, folder: &str, with_tab_stops: bool) {
+    for violation in &violations.all() {
+        let image_names = violation_image_names(test_sets, violation, "svg");
+        if let &LayoutViolation::AlignmentLost { .. } = violation {
+            let svg = generate_alignment_lost_svg(test_sets, violation, screen_size_names, with_tab_stops);
+            save_svg(&format!("output/{}/{}", folder, image_names[0]), &svg);
+            continue;
+        }
+        let violation_test_sets = violation.test_sets();
+        for (ts, image_name) in violation_test_sets.iter().zip(image_names.iter()) {
+            let test_set = match test_sets.sets.get(ts) {
+                Some(test_set) => test_set,
+                None => continue,
+            };
+            let svg = generate_svg(test_set, &vec![violation], with_tab_stops);
+            save_svg(&format!("output/{}/{}", folder, image_name), &svg);
+        }
+    }
+}
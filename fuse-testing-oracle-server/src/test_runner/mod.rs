@@ -1,80 +1,139 @@
 pub mod test_script;
 
-use test_script::*;
+use self::test_script::*;
 use std::thread;
 
 use std::collections::HashSet;
-use std::process::{Command,Child};
+use std::process::{Command,Child,ExitStatus,Stdio};
 use std::thread::spawn;
+use std::io::{Read,BufRead,BufReader};
 
 use std::path::{Path,PathBuf};
 
 use super::layout_validator::{Nodes,TabStops};
+use super::test_sets::TestSetId;
+use super::server::{Server,Session,ServerError,LayoutMismatch};
+use super::protocol::SESSION_ENV_VAR;
+use super::app_log::{AppLog,LogSource};
 
 #[cfg(target_os = "windows")]
 use winapi::windef::{HWND,HBITMAP};
 #[cfg(target_os = "windows")]
-use winapi::minwindef::{LPARAM, BOOL, LPDWORD};
-#[cfg(target_os = "windows")]
 use winapi::wingdi::{HORZSIZE,VERTSIZE,SRCCOPY,BITMAP,LPBITMAPINFO,BITMAPINFO};
 #[cfg(target_os = "windows")]
-use user32::{EnumWindows, GetWindowThreadProcessId, MoveWindow, PrintWindow, GetDC};
+use user32::{PrintWindow, GetDC};
 #[cfg(target_os = "windows")]
 use gdi32::{CreateCompatibleDC,CreateCompatibleBitmap,GetDeviceCaps,BitBlt,SelectObject,DeleteDC,GetPixel,GetDIBits};
-#[cfg(target_os = "windows")]
-use kernel32::GetLastError;
-
-use std::mem::transmute;
-use std::sync::mpsc::channel;
 
-pub enum TestInstruction {
-    ResizeWindow(i32, i32, i32, i32)
+use std::sync::mpsc::{channel,Receiver,RecvTimeoutError};
+use std::time::{Duration,Instant};
+use std::fmt;
+
+//how long we wait after a resize for apps that can't tell us when their layout is ready
+const RESIZE_SETTLE_FALLBACK_MS: u64 = 400;
+//the same for the first layout after the app has started
+const STARTUP_SETTLE_FALLBACK_MS: u64 = 500;
+//how many times an app is restarted after crashing before we give up on it
+const MAX_RESTARTS: usize = 3;
+/*How long we wait for the rest of the app's output once it has been killed. A child process the
+app started can keep the pipes open after the app is gone, so the readers may never finish.*/
+const OUTPUT_DRAIN_TIMEOUT_MS: u64 = 2000;
+
+//the app crashed or hung while a screen size was being captured
+#[derive(Debug,Clone)]
+pub struct AppCrash {
+    pub test_set: TestSetId,
+    pub screen_size: String,
+    //how the process ended, None if it was still running but stopped answering
+    pub exit_status: Option<String>,
+    pub error: ServerError,
 }
 
-#[cfg(target_os = "windows")]
-static mut FUSE_WINDOW_HANDLE: Option<HWND> = None;
-
-#[cfg(target_os = "windows")]
-unsafe extern "system" fn window_enum_proc(window_handle: HWND, param: LPARAM) -> BOOL {
-
-    let mut process_id : u32 = 1;
-    let mut window_process: LPDWORD = &mut process_id as *mut u32;
-    let thread_id = GetWindowThreadProcessId(window_handle, window_process);
-
-    if param == process_id as i64 {
-        //println!("we found our window handle");
-        //println!("Process for window handle {:?} is {:?}, we are looking for {:?}", window_handle, process_id, param);
-
-        /*let mut zorder = 0;
-        let topmost = zorder as HWND;
-        SetWindowPos(window_handle, topmost, 100, 100, 200, 200, 0x0040);*/
-
-        FUSE_WINDOW_HANDLE = Some(window_handle.clone());
-        return 0;
+impl fmt::Display for AppCrash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.exit_status {
+            Some(ref status) => write!(f, "{} => the app exited with {} ({})", self.screen_size, status, self.error),
+            None => write!(f, "{} => the app stopped answering ({})", self.screen_size, self.error),
+        }
     }
-    1
 }
 
-#[cfg(target_os = "windows")]
-fn set_window_size_for_proc_id(proc_id: u32, pos: (i32, i32), size: (i32, i32)) -> i64 {
-    unsafe {
-        EnumWindows(Some(window_enum_proc), proc_id as i64);
-
-        if let Some(window_handle) = FUSE_WINDOW_HANDLE {
+//what came out of test_screen_sizes, besides the layouts the closure was given
+pub struct ScreenSizeResults {
+    //screen sizes that could not be captured, with the id their test set would have had
+    pub failures: Vec<(TestSetId, String, ServerError)>,
+    pub crashes: Vec<AppCrash>,
+    //layouts discarded by all the sessions used, see Session::take_mismatches
+    pub mismatches: Vec<LayoutMismatch>,
+}
 
-            let moved_result = MoveWindow(window_handle, pos.0, pos.1, size.0, size.1, 0);
-            let last_error = GetLastError();
+impl ScreenSizeResults {
+    fn new() -> ScreenSizeResults {
+        ScreenSizeResults {
+            failures: Vec::new(),
+            crashes: Vec::new(),
+            mismatches: Vec::new(),
+        }
+    }
 
-            return transmute::<HWND,i64>(window_handle)
-        } else {
-            panic!("Could not move window");
+    fn fail_remaining(&mut self, screen_sizes: &[(i32, &ScreenSize)], error: &ServerError) {
+        for &(id, screen_size) in screen_sizes {
+            self.failures.push((TestSetId(id), screen_size.name.clone(), error.clone()));
         }
     }
-    panic!("Coult not move window_");
 }
 
 pub struct TestRunnerContext {
-    process: Child
+    process: Child,
+    //kept so the app can be restarted after a crash
+    exe_path: PathBuf,
+    arguments: Vec<String>,
+    //what the app printed and logged, shared by all the processes started for it
+    log: AppLog,
+    //each one hangs up when its reader is done
+    output_readers: Vec<Receiver<()>>,
+}
+
+//reads the app's output line by line into the log, until the app closes it
+fn read_output<R: Read + Send + 'static>(output: R, source: LogSource, log: AppLog) -> Receiver<()> {
+    let (done_tx, done_rx) = channel::<()>();
+    spawn(move || {
+        let _done = done_tx;
+        let mut reader = BufReader::new(output);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    //the app is free to print whatever it wants, so this must not fail on bad utf8
+                    let text = String::from_utf8_lossy(&line).trim_right().to_string();
+                    log.add(source, text);
+                }
+            }
+        }
+    });
+    done_rx
+}
+
+fn spawn_app(exe_path: &Path, arguments: &Vec<String>, session_key: &str, log: &AppLog) -> (Child, Vec<Receiver<()>>) {
+    println!("starting {:?} with arguments {:?} for session {}", exe_path, arguments, session_key);
+    let mut process = Command::new(exe_path)
+        .args(arguments)
+        .env(SESSION_ENV_VAR, session_key)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut output_readers = Vec::new();
+    if let Some(stdout) = process.stdout.take() {
+        output_readers.push(read_output(stdout, LogSource::Stdout, log.clone()));
+    }
+    if let Some(stderr) = process.stderr.take() {
+        output_readers.push(read_output(stderr, LogSource::Stderr, log.clone()));
+    }
+    (process, output_readers)
 }
 
 pub fn build_project(project_path: &Path) {
@@ -95,26 +154,74 @@ pub fn build_project(project_path: &Path) {
 
 impl TestRunnerContext {
 
-    pub fn start_preview_for_example(project_path: &Path) -> TestRunnerContext {
+    pub fn start_preview_for_example(project_path: &Path, session_key: &str) -> TestRunnerContext {
         println!("starting preview process");
 
         println!("We have project path");
         let path_string = project_path.to_str().unwrap();
 
-        let process = if cfg!(any(target_os = "windows", target_os = "macos")) {
+        if cfg!(any(target_os = "windows", target_os = "macos")) {
             println!("program path: {}", &path_string);
-            Command::new(&path_string).spawn() //since we need to use uno instead of fuse preview (for testing), we need to start the compiled exe
+            //since we need to use uno instead of fuse preview (for testing), we need to start the compiled exe
+            TestRunnerContext::start_executable(project_path, &Vec::new(), session_key)
         } else {
             panic!("unsupported os");
-        };
+        }
+    }
+
+    /*The session key tells the server which session the app belongs to. The app's stdout and
+    stderr are captured into its log instead of being mixed into ours.*/
+    pub fn start_executable(exe_path: &Path, arguments: &Vec<String>, session_key: &str) -> TestRunnerContext {
+        let log = AppLog::new();
+        let (process, output_readers) = spawn_app(exe_path, arguments, session_key, &log);
 
         TestRunnerContext {
-            process: process.unwrap()
+            process: process,
+            exe_path: exe_path.to_path_buf(),
+            arguments: arguments.clone(),
+            log: log,
+            output_readers: output_readers,
         }
     }
 
+    /*Kills the app, and waits until everything it printed is in the log. Readers that are still
+    going after OUTPUT_DRAIN_TIMEOUT_MS are left behind, they end whenever the pipe is closed.*/
     pub fn kill_process(&mut self) {
         self.process.kill();
+        self.process.wait();
+        let deadline = Instant::now() + Duration::from_millis(OUTPUT_DRAIN_TIMEOUT_MS);
+        for output_reader in self.output_readers.drain(..) {
+            let now = Instant::now();
+            let remaining = if deadline > now { deadline - now } else { Duration::from_millis(0) };
+            match output_reader.recv_timeout(remaining) {
+                Err(RecvTimeoutError::Timeout) => println!("The app's output is still open after it was killed, not waiting for the rest of it"),
+                _ => (),
+            }
+        }
+    }
+
+    pub fn log(&self) -> &AppLog {
+        &self.log
+    }
+
+    //Some once the app has exited, or crashed
+    pub fn exit_status(&mut self) -> Option<ExitStatus> {
+        match self.process.try_wait() {
+            Ok(status) => status,
+            Err(e) => {
+                println!("Could not check on the app: {}", e);
+                None
+            }
+        }
+    }
+
+    //kills the app if it still runs, and starts it again for the given session
+    pub fn restart(&mut self, session_key: &str) {
+        println!("Restarting the app");
+        self.kill_process();
+        let (process, output_readers) = spawn_app(&self.exe_path, &self.arguments, session_key, &self.log);
+        self.process = process;
+        self.output_readers = output_readers;
     }
 
     pub fn build_and_get_exe_path(project_path: &Path) -> (PathBuf, String) {
@@ -169,24 +276,111 @@ impl TestRunnerContext {
         }*/
     }
 
-    pub fn test_all_screen_sizes<F>(&mut self, test_script: &TestScript, mut request_layout: F)
-        where F: FnMut(i32, (i32,i32)) {
-
-        let mut id = 0;
-        for screen_size in &test_script.screen_sizes {
-
-            let ss = ((screen_size.width as f32 / screen_size.pixels_per_point) as i32, (screen_size.height as f32 / screen_size.pixels_per_point) as i32);
-
-            println!("Testing screen size: {:?}", ss);
-
-            let proc_id = self.process.id();
-
-            println!("Proc id: {}", proc_id);
+    //connects the session to the app just started, and gives its first layout time to settle
+    pub fn connect(&mut self, session: &mut Session) -> Result<(), ServerError> {
+        session.set_log(self.log.clone());
+        session.wait_for_client_while(|| self.exit_status().is_none())?;
+        println!("Client connected");
+        if let Err(e) = session.wait_for_layout_ready(Duration::from_millis(STARTUP_SETTLE_FALLBACK_MS)) {
+            println!("Waiting for the first layout failed: {}", e);
+        }
+        Ok(())
+    }
 
-            let window_handle = set_window_size_for_proc_id(proc_id, (0,0), ss);
+    fn capture_screen_size<F>(session: &Session, id: i32, screen_size: &ScreenSize, request_layout: &mut F) -> Result<(), ServerError>
+        where F: FnMut(&Session, i32, &ScreenSize) -> Result<(), ServerError> {
+        let ss = ((screen_size.width as f32 / screen_size.pixels_per_point) as i32, (screen_size.height as f32 / screen_size.pixels_per_point) as i32);
+
+        println!("Testing screen size: {:?}", ss);
+
+        //the app resizes its own root, so this works the same on every platform
+        session.resize_window(ss).and_then(|new_size| {
+            if new_size != ss {
+                println!("App was asked for {:?} but ended up at {:?}", ss, new_size);
+            }
+            session.wait_for_layout_ready(Duration::from_millis(RESIZE_SETTLE_FALLBACK_MS))
+        }).and_then(|ready| {
+            if !ready {
+                println!("Capturing {} although its layout has not settled", screen_size.name);
+            }
+            request_layout(session, id, screen_size)
+        })
+    }
 
-            request_layout(id, (screen_size.width, screen_size.height));
-            id += 1;
+    /*Captures the given screen sizes, each paired with the id its test set gets, with the app
+    connected to the given session. If the app crashes or hangs, it is restarted on a new session
+    (which replaces the given one) and the screen size that was in progress is tried again.
+    A screen size that crashes the app twice in a row is given up on, and so are all the
+    remaining ones after MAX_RESTARTS restarts.*/
+    pub fn test_screen_sizes<F>(&mut self, server: &Server, session: &mut Session, screen_sizes: &Vec<(i32, &ScreenSize)>, mut request_layout: F) -> ScreenSizeResults
+        where F: FnMut(&Session, i32, &ScreenSize) -> Result<(), ServerError> {
+
+        let mut results = ScreenSizeResults::new();
+        let mut restarts = 0;
+        let mut crashed_at = None;
+        let mut i = 0;
+
+        while i < screen_sizes.len() {
+            let (id, screen_size) = screen_sizes[i];
+            self.log.set_screen_size(Some(screen_size.name.clone()));
+
+            let e = match TestRunnerContext::capture_screen_size(session, id, screen_size, &mut request_layout) {
+                Ok(()) => {
+                    i += 1;
+                    continue;
+                },
+                Err(e) => e,
+            };
+            println!("Could not capture screen size {}: {}", screen_size.name, e);
+
+            if let ServerError::ServerStopped = e {
+                results.fail_remaining(&screen_sizes[i..], &e);
+                break;
+            }
+
+            //a timeout from an app that still runs means it hung, which is handled like a crash
+            let exit_status = self.exit_status();
+            match exit_status {
+                Some(status) => println!("The app crashed while capturing {} ({})", screen_size.name, status),
+                None => println!("The app hung while capturing {}", screen_size.name),
+            }
+            results.crashes.push(AppCrash {
+                test_set: TestSetId(id),
+                screen_size: screen_size.name.clone(),
+                exit_status: exit_status.map(|status| format!("{}", status)),
+                error: e.clone(),
+            });
+            if crashed_at == Some(i) {
+                //the app crashed or hung on this one the last time too, retrying would only do it again
+                results.failures.push((TestSetId(id), screen_size.name.clone(), e.clone()));
+                i += 1;
+                crashed_at = None;
+            } else {
+                crashed_at = Some(i);
+            }
+
+            if i >= screen_sizes.len() {
+                break;
+            }
+            if restarts == MAX_RESTARTS {
+                println!("The app was restarted {} times already, giving up on the remaining screen sizes", restarts);
+                results.fail_remaining(&screen_sizes[i..], &e);
+                break;
+            }
+            restarts += 1;
+
+            results.mismatches.extend(session.take_mismatches());
+            *session = server.new_session();
+            self.restart(&session.key());
+            if let Err(e) = self.connect(session) {
+                println!("The restarted app did not connect: {}", e);
+                results.fail_remaining(&screen_sizes[i..], &e);
+                break;
+            }
         }
+
+        self.log.set_screen_size(None);
+        results.mismatches.extend(session.take_mismatches());
+        results
     }
 }
@@ -1,4 +1,6 @@
 use serde_yaml;
+use layout_validator::rules::{RulesConfig,merge_rules_config};
+use suppressions::Suppression;
 use std::fs::File;
 use std::io::Read;
 
@@ -12,6 +14,7 @@ fn pixels_per_point_default() -> f32 { 1.0 }
 fn physical_pixels_per_inch_default() -> f32 { 1.0 }
 fn physical_pixels_per_pixel_default() -> f32 { 1.0 }
 fn default_orientation_default() -> ScreenOrientation { ScreenOrientation::Portrait }
+fn instances_default() -> usize { 1 }
 
 #[derive(Deserialize)]
 pub struct ScreenSize {
@@ -31,12 +34,24 @@ pub struct ScreenSize {
 #[derive(Deserialize)]
 pub struct Project {
     #[serde(rename = "Path")] pub path: String,
+    //when set, this executable is started as is instead of building the project with uno (used for the mock client)
+    #[serde(rename = "Executable", default)] pub executable: Option<String>,
+    #[serde(rename = "Arguments", default)] pub arguments: Vec<String>,
+    //how many copies of the app to run at once, the screen sizes are split between them
+    #[serde(rename = "Instances", default = "instances_default")] pub instances: usize,
+    //rule settings for this project only, on top of the ones for the whole test script
+    #[serde(rename = "Rules", default)] pub rules: RulesConfig,
+    //known and accepted violations in this project, in addition to the ones for the whole test script
+    #[serde(rename = "Suppressions", default)] pub suppressions: Vec<Suppression>,
 }
 
 #[derive(Deserialize)]
 pub struct TestScript {
     #[serde(rename = "ScreenSizes")] pub screen_sizes: Vec<ScreenSize>,
     #[serde(rename = "Projects")] pub projects: Vec<Project>,
+    //rule name -> settings, see layout_validator::rules::RuleConfig
+    #[serde(rename = "Rules", default)] pub rules: RulesConfig,
+    #[serde(rename = "Suppressions", default)] pub suppressions: Vec<Suppression>,
 }
 
 fn parse_test_script(path: &str) -> Option<TestScript> {
@@ -61,4 +76,16 @@ impl TestScript {
         }
         ret
     }
+
+    //the rule settings used when validating the given project
+    pub fn rules_for(&self, project: &Project) -> RulesConfig {
+        merge_rules_config(&self.rules, &project.rules)
+    }
+
+    //the suppressions used when validating the given project
+    pub fn suppressions_for(&self, project: &Project) -> Vec<Suppression> {
+        let mut suppressions = self.suppressions.clone();
+        suppressions.extend(project.suppressions.iter().cloned());
+        suppressions
+    }
 }
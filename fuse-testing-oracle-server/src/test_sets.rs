@@ -1,9 +1,10 @@
 use layout_validator::*;
 use std::collections::HashMap;
 use layout_validator::*;
+use layout_validator::stability::UnstableNodes;
 use std::fmt::{Display,Formatter,Result,Write};
 
-#[derive(Eq, PartialEq, Hash, Debug,Clone, Copy)]
+#[derive(Serialize, Eq, PartialEq, Hash, Debug,Clone, Copy)]
 pub struct TestSetId(pub i32);
 
 impl Display for TestSetId {
@@ -18,6 +19,8 @@ pub struct TestSet {
     pub screen_size: (i32,i32),
     pub nodes: Nodes,
     pub tab_stops: TabStops,
+    //nodes that moved between repeated captures, they are left out of the checks
+    pub unstable_nodes: UnstableNodes,
 }
 
 #[derive(Clone)]
@@ -25,11 +28,23 @@ pub struct TestSets {
     pub sets: HashMap<TestSetId, TestSet>,
 }
 
-pub fn find_tab_stops_for_nodes(nodes: &Nodes) -> TabStops {
+impl TestSets {
+    //the test sets in the order of their ids, so what is made from them comes out the same every run
+    pub fn sorted(&self) -> Vec<&TestSet> {
+        let mut sorted: Vec<&TestSet> = self.sets.values().collect();
+        sorted.sort_by_key(|ts| ts.id.0);
+        sorted
+    }
+}
+
+pub fn find_tab_stops_for_nodes(nodes: &Nodes, unstable_nodes: &UnstableNodes) -> TabStops {
     let mut tab_stops = TabStops::new();
 
     let mut c = 0;
     for (id,n) in &nodes.nodes {
+        if unstable_nodes.contains_key(id) {
+            continue;
+        }
         let data = &n.node_data;
         tab_stops.insert_unique(TabStop::new(data.render_position_x, Orientation::Vertical), NodeSide::new(n.id, Side::Left));
         tab_stops.insert_unique(TabStop::new(data.render_position_y, Orientation::Horizontal), NodeSide::new(n.id, Side::Top));
@@ -42,16 +57,46 @@ pub fn find_tab_stops_for_nodes(nodes: &Nodes) -> TabStops {
     tab_stops
 }
 
+//the merge threshold used when the test script doesn't set a Tolerance for AlignmentLost
+pub const DEFAULT_TAB_STOP_MERGE_THRESHOLD: f32 = 10.0;
+
+/*Tab stops that are at most tab_stop_merge_threshold points apart are merged into the first
+of them, so node sides that are a point or two off still count as aligned. Each group is
+measured from its first tab stop, so groups don't creep along a row of close tab stops.*/
 pub fn merge_tab_stops(tab_stops: &mut TabStops, tab_stop_merge_threshold: f32) {
-    //TODO
+    if tab_stop_merge_threshold <= 0.0 {
+        return;
+    }
 
+    let mut merged = TabStops::new();
+    let mut anchor: Option<TabStop> = None;
+    for tab_stop in tab_stops.sorted() {
+        let target = match anchor {
+            Some(a) if a.orientation == tab_stop.orientation
+                && (tab_stop.pos - a.pos) as f32 <= tab_stop_merge_threshold => a,
+            _ => {
+                anchor = Some(tab_stop);
+                tab_stop
+            }
+        };
+        if let Some(node_sides) = tab_stops.get_nodes(&tab_stop) {
+            for node_side in node_sides {
+                merged.insert_unique(target, node_side.clone());
+            }
+        }
+    }
+
+    *tab_stops = merged;
 }
 
-pub fn generate_test_sets(test_data: Vec<(TestSetId, Nodes, (i32,i32))>, tab_stop_merge_threshold: f32) -> TestSets {
+pub fn generate_test_sets(test_data: Vec<(TestSetId, Nodes, (i32,i32))>,
+                          mut unstable_nodes: HashMap<TestSetId, UnstableNodes>,
+                          tab_stop_merge_threshold: f32) -> TestSets {
     let mut test_sets = HashMap::new();
     for (id, nodes, screen_size) in test_data {
+        let unstable_nodes = unstable_nodes.remove(&id).unwrap_or(UnstableNodes::new());
 
-        let mut tab_stops = find_tab_stops_for_nodes(&nodes);
+        let mut tab_stops = find_tab_stops_for_nodes(&nodes, &unstable_nodes);
         merge_tab_stops(&mut tab_stops, tab_stop_merge_threshold);
 
         let test_set = TestSet {
@@ -59,6 +104,7 @@ pub fn generate_test_sets(test_data: Vec<(TestSetId, Nodes, (i32,i32))>, tab_sto
             id: id.clone(),
             nodes: nodes,
             tab_stops: tab_stops,
+            unstable_nodes: unstable_nodes,
         };
         test_sets.insert(id, test_set);
     }
@@ -66,3 +112,50 @@ pub fn generate_test_sets(test_data: Vec<(TestSetId, Nodes, (i32,i32))>, tab_sto
         sets: test_sets
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tab_stops(positions: &[(i32, Orientation, i32)]) -> TabStops {
+        let mut tab_stops = TabStops::new();
+        for &(pos, ref orientation, node) in positions {
+            let side = match orientation {
+                &Orientation::Vertical => Side::Left,
+                &Orientation::Horizontal => Side::Top,
+            };
+            tab_stops.insert_unique(TabStop::new(pos, orientation.clone()), NodeSide::new(node, side));
+        }
+        tab_stops
+    }
+
+    fn nodes_at(tab_stops: &TabStops, pos: i32, orientation: Orientation) -> Vec<i32> {
+        let mut nodes: Vec<i32> = tab_stops.get_nodes(&TabStop::new(pos, orientation)).unwrap()
+            .iter().map(|node_side| node_side.node).collect();
+        nodes.sort();
+        nodes
+    }
+
+    #[test]
+    fn merge_tab_stops_groups_from_the_first_tab_stop() {
+        let mut merged = tab_stops(&[(0, Orientation::Vertical, 1), (5, Orientation::Vertical, 2),
+                                     (12, Orientation::Vertical, 3), (3, Orientation::Horizontal, 4)]);
+        merge_tab_stops(&mut merged, 10.0);
+
+        //12 is within 10 of 5 but not of 0, where the group starts
+        assert_eq!(merged.count(), 3);
+        assert_eq!(nodes_at(&merged, 0, Orientation::Vertical), vec![1, 2]);
+        assert_eq!(nodes_at(&merged, 12, Orientation::Vertical), vec![3]);
+        //tab stops of another orientation are never merged
+        assert_eq!(nodes_at(&merged, 3, Orientation::Horizontal), vec![4]);
+    }
+
+    #[test]
+    fn merge_tab_stops_without_threshold() {
+        let original = tab_stops(&[(0, Orientation::Vertical, 1), (1, Orientation::Vertical, 2)]);
+        let mut merged = original.clone();
+        merge_tab_stops(&mut merged, 0.0);
+        assert!(merged == original);
+        assert_eq!(merged.count(), 2);
+    }
+}
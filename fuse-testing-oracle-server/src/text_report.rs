@@ -0,0 +1,155 @@
+use layout_validator::validity_rules::{LayoutViolation,LayoutViolations};
+use layout_validator::rules::Severity;
+use test_sets::*;
+use server::{ServerError,LayoutMismatch};
+use test_runner::AppCrash;
+use std::fmt::Write;
+
+//lists what could not be captured, so a partial report is not mistaken for a clean one
+pub fn generate_failures_report(failures: &Vec<(TestSetId, String, ServerError)>, mismatches: &Vec<LayoutMismatch>, crashes: &Vec<AppCrash>) -> String {
+    let mut ret = String::new();
+
+    if crashes.len() > 0 {
+        writeln!(ret, "* App crashes: - {}", crashes.len());
+        for crash in crashes {
+            writeln!(ret, "- Crashed - {}", crash);
+        }
+    }
+
+    if failures.len() > 0 {
+        writeln!(ret, "* Failed captures: - {}", failures.len());
+        for &(_, ref what, ref error) in failures {
+            writeln!(ret, "- Failed - {} => {}", what, error);
+        }
+    }
+
+    if mismatches.len() > 0 {
+        writeln!(ret, "* Discarded layouts: - {}", mismatches.len());
+        for mismatch in mismatches {
+            writeln!(ret, "- Discarded - {}", mismatch);
+        }
+    }
+
+    println!("{}", ret);
+
+    ret
+}
+
+pub fn generate_violations_report(test_sets: &TestSets, violations: &LayoutViolations) -> String {
+    let mut ret = String::new();
+
+
+    let mut sorted_by_test_set: Vec<LayoutViolation> = violations.all();
+
+    let violation_type_counts = sorted_by_test_set.iter().fold((0,0,0,0,0), |acc, v| {
+        match v {
+            &LayoutViolation::Overlap { .. } => (acc.0 + 1,acc.1,acc.2,acc.3,acc.4),
+            &LayoutViolation::Overflow { .. } => (acc.0, acc.1 + 1, acc.2, acc.3, acc.4),
+            &LayoutViolation::AlignmentLost { .. } => (acc.0, acc.1, acc.2 + 1, acc.3, acc.4),
+            &LayoutViolation::UnstableLayout { .. } => (acc.0, acc.1, acc.2, acc.3 + 1, acc.4),
+            &LayoutViolation::Custom(_) => (acc.0, acc.1, acc.2, acc.3, acc.4 + 1),
+        }
+    });
+
+    //the rules that are not built in are counted by kind, in the order they first show up
+    let mut custom_counts: Vec<(String, i32)> = Vec::new();
+    for v in &sorted_by_test_set {
+        if let &LayoutViolation::Custom(ref custom) = v {
+            match custom_counts.iter().position(|&(ref kind, _)| *kind == custom.kind) {
+                Some(index) => custom_counts[index].1 += 1,
+                None => custom_counts.push((custom.kind.clone(), 1)),
+            }
+        }
+    }
+
+    let total_errors = violation_type_counts.0 + violation_type_counts.1 + violation_type_counts.2 + violation_type_counts.3 + violation_type_counts.4;
+    let total_overlaps = violation_type_counts.0;
+    let total_overflows = violation_type_counts.1;
+    let total_layout_changes = violation_type_counts.2;
+    let total_unstable_layouts = violation_type_counts.3;
+
+    let mut lines = Vec::new();
+
+
+
+    let mut test_set = -1;
+    let mut violation_id = 0;
+    for violation in &sorted_by_test_set {
+        match violation {
+            &LayoutViolation::Overlap { node1, node2, intersection_rect, test_set, severity } => {
+                let ts = test_sets.sets.get(&test_set).unwrap();
+
+                let n1 = ts.nodes.get_from_id(node1).unwrap();
+                let n2 = ts.nodes.get_from_id(node2).unwrap();
+                lines.push(format!("- Overlap ({}) - test-set:{} => {} - {}", severity, test_set, n1, n2));
+            },
+            &LayoutViolation::Overflow { node1, node2, ref overflow_rect, test_set, severity } => {
+                let ts = test_sets.sets.get(&test_set).unwrap();
+
+                let n1 = ts.nodes.get_from_id(node1).unwrap();
+                let n2 = ts.nodes.get_from_id(node2).unwrap();
+                lines.push(format!("- Overflow ({}) - test-set:{} => {} - {}", severity, test_set.0, n1, n2));
+            },
+            &LayoutViolation::AlignmentLost { a:a, b:b, count:count, test_sets: ref tss, severity } => {
+                let ts = test_sets.sets.get(&tss.get(0).unwrap()).unwrap();
+
+                let n1_line = ts.nodes.get_from_id(a.node).unwrap().node_data.line;
+                let n2_line = ts.nodes.get_from_id(b.node).unwrap().node_data.line;
+
+                let mut l = String::new();
+
+                write!(&mut l, "- AlignmentLost ({}) => {} @ L:{} - {} @ L:{} - aligned in {} test sets", severity, a, n1_line, b, n2_line, count);
+                lines.push(l);
+            },
+            &LayoutViolation::UnstableLayout { test_set, node, ref rects, severity } => {
+                let ts = test_sets.sets.get(&test_set).unwrap();
+
+                //a node that disappeared in the last capture is not in the test set, only its id is left
+                let n = match ts.nodes.get_from_id(node) {
+                    Some(n) => format!("{}", n),
+                    None => format!("node {}", node),
+                };
+                //rects has one entry for each capture the node was found in
+                lines.push(format!("- UnstableLayout ({}) - test-set:{} => {} changed between captures and was found in {} of them", severity, test_set.0, n, rects.len()));
+            },
+            &LayoutViolation::Custom(ref custom) => {
+                let test_set_ids: Vec<String> = custom.test_sets.iter().map(|ts| format!("{}", ts.0)).collect();
+                lines.push(format!("- {} ({}) - test-sets:{} => {}", custom.kind, custom.severity, test_set_ids.join(","), custom.message));
+            },
+        }
+        violation_id += 1;
+    }
+
+    writeln!(ret, "* # of test sets: - {}", test_sets.sets.iter().len());
+    writeln!(ret, "* Total errors: - {}", total_errors);
+    writeln!(ret, "* Suppressed: - {}", violations.suppressed.len());
+    let count_of = |severity: Severity| sorted_by_test_set.iter().filter(|v| v.severity() == severity).count();
+    writeln!(ret, "* By severity: - error: {}, warning: {}, info: {}",
+             count_of(Severity::Error), count_of(Severity::Warning), count_of(Severity::Info));
+    writeln!(ret, "\t* Overlaps ------------- : {}", total_overlaps);
+    writeln!(ret, "\t* Overflows ------------ : {}", total_overflows);
+    writeln!(ret, "\t* Total alignment changes: {}", total_layout_changes);
+    writeln!(ret, "\t* Unstable layouts ------ : {}", total_unstable_layouts);
+    for &(ref kind, count) in &custom_counts {
+        writeln!(ret, "\t* {}: {}", kind, count);
+    }
+
+    for l in lines {
+        writeln!(&mut ret, "{}", l);
+    }
+
+    if violations.suppressed.len() > 0 {
+        writeln!(ret, "* Suppressed violations:");
+        for &(ref violation, ref suppression) in &violations.suppressed {
+            let description = match violation.test_sets().get(0).and_then(|id| test_sets.sets.get(id)) {
+                Some(ts) => violation.describe(ts),
+                None => format!("{}", violation),
+            };
+            writeln!(ret, "- {} - suppressed by {}", description, suppression);
+        }
+    }
+
+    println!("{}",ret);
+
+    ret
+}
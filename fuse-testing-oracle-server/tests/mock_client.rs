@@ -0,0 +1,102 @@
+/*Runs the server against the mock client with generated layouts, the way an auto run does, and
+checks the test sets made from what it captured.*/
+
+extern crate fuse_testing_oracle_server;
+extern crate serde_yaml;
+
+use fuse_testing_oracle_server::server::Server;
+use fuse_testing_oracle_server::protocol::{CAPABILITY_RESIZE_WINDOW, CAPABILITY_LAYOUT_READY};
+use fuse_testing_oracle_server::test_runner::TestRunnerContext;
+use fuse_testing_oracle_server::test_runner::test_script::ScreenSize;
+use fuse_testing_oracle_server::test_sets::{generate_test_sets, TestSetId};
+use fuse_testing_oracle_server::layout_validator::validate_layout;
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+const ADDRESS: &'static str = "127.0.0.1:12399";
+
+fn screen_size(name: &str, width: i32, height: i32, pixels_per_point: f32) -> ScreenSize {
+    serde_yaml::from_str(&format!("Name: {}\nWidth: {}\nHeight: {}\nPixelsPerPoint: {}", name, width, height, pixels_per_point)).unwrap()
+}
+
+#[test]
+fn captures_generated_layouts() {
+    let mut server = Server::start_new(ADDRESS);
+    server.set_connect_timeout(Duration::from_secs(10));
+    server.set_response_timeout(Duration::from_secs(5));
+    //the mock client answers at once, so a missed answer fails the test instead of waiting it out
+    server.set_layout_ready_timeout(Duration::from_secs(5));
+
+    let screen_sizes = vec![
+        screen_size("narrow", 640, 1136, 2.0),
+        screen_size("phone", 750, 1334, 2.0),
+        screen_size("tablet", 1536, 2048, 1.5),
+    ];
+    let ids: Vec<(i32, &ScreenSize)> = screen_sizes.iter().enumerate().map(|(i, s)| (i as i32, s)).collect();
+
+    let arguments: Vec<String> = vec!["--address", ADDRESS, "--generate", "42"].into_iter().map(String::from).collect();
+    let mut session = server.new_session();
+    let mut context = TestRunnerContext::start_executable(Path::new(env!("CARGO_BIN_EXE_mock_client")), &arguments, &session.key());
+    context.connect(&mut session).unwrap();
+
+    {
+        let client_info = session.client_info().unwrap();
+        assert!(client_info.has_capability(CAPABILITY_RESIZE_WINDOW));
+        assert!(client_info.has_capability(CAPABILITY_LAYOUT_READY));
+    }
+
+    let mut test_data = Vec::new();
+    let mut layout_ready = Vec::new();
+    let results = context.test_screen_sizes(&server, &mut session, &ids, |session, id, screen_size| {
+        //the app is asked again, it answers with the id it was sent when its layout has settled
+        layout_ready.push(session.wait_for_layout_ready(Duration::from_millis(0))?);
+        let (nodes, id, _) = session.request_layout_data(id)?;
+        test_data.push((TestSetId(id), nodes, (screen_size.width, screen_size.height)));
+        Ok(())
+    });
+    context.kill_process();
+
+    assert_eq!(results.failures.len(), 0);
+    assert_eq!(results.crashes.len(), 0);
+    assert_eq!(results.mismatches.len(), 0);
+    assert_eq!(layout_ready, vec![true, true, true]);
+
+    //the app tells the server about each resize in its log
+    let log: Vec<String> = context.log().lines().iter().map(|line| format!("{}", line)).collect();
+    assert!(log.iter().any(|line| line == "[narrow] client: resized to 320x568"), "{:?}", log);
+    assert!(log.iter().any(|line| line == "[tablet] client: resized to 1024x1365"), "{:?}", log);
+
+    let test_sets = generate_test_sets(test_data, HashMap::new(), 0.0);
+    assert_eq!(test_sets.sets.len(), 3);
+
+    //the generated layouts have the same structure on every size, only the geometry changes
+    let mut names = Vec::new();
+    for &(id, screen_size) in &ids {
+        let test_set = &test_sets.sets[&TestSetId(id)];
+        assert_eq!(test_set.screen_size, (screen_size.width, screen_size.height));
+
+        let root = test_set.nodes.root_node().unwrap();
+        assert_eq!(test_set.nodes.get_from_id(root).unwrap().node_data.name, "Root");
+        let root_rect = test_set.nodes.rect_of(&root);
+        let points = ((screen_size.width as f32 / screen_size.pixels_per_point) as u32, (screen_size.height as f32 / screen_size.pixels_per_point) as u32);
+        assert_eq!((root_rect.width(), root_rect.height()), points);
+
+        let mut node_names: Vec<(i32, String)> = test_set.nodes.sorted_by_line().iter()
+            .map(|n| (n.id, n.node_data.name.clone()))
+            .collect();
+        node_names.sort();
+        names.push(node_names);
+    }
+    assert_eq!(names[0], names[1]);
+    assert_eq!(names[0], names[2]);
+
+    //all violations are found in test sets that were captured
+    let violations = validate_layout(&test_sets);
+    for violation in violations.all() {
+        for id in violation.test_sets() {
+            assert!(test_sets.sets.contains_key(&id));
+        }
+    }
+}